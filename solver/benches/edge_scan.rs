@@ -0,0 +1,39 @@
+//! The edge-aware border distance against the vertex-only scan it replaced
+//! (`--vertex-only`), on synthetic outlines of growing detail.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use geo::{MultiPolygon, Point, Polygon};
+use untitled3::distance::{calculate_edge_distance_km, calculate_min_distance_km, Border, DistanceModel};
+
+/// A country-sized wobbly ring of `vertices` points around `centre`.
+fn outline(centre: (f64, f64), vertices: usize) -> (Vec<Point<f64>>, MultiPolygon<f64>) {
+    let ring: Vec<(f64, f64)> = (0..=vertices)
+        .map(|i| {
+            let angle = i as f64 / vertices as f64 * std::f64::consts::TAU;
+            let radius = 3.0 + 0.5 * (7.0 * angle).sin();
+            (centre.0 + radius * angle.cos(), centre.1 + radius * angle.sin())
+        })
+        .collect();
+    let polygon = Polygon::new(ring.into(), Vec::new());
+    (polygon.exterior().points().collect(), MultiPolygon::new(vec![polygon]))
+}
+
+fn border_distance(c: &mut Criterion) {
+    let mut group = c.benchmark_group("border distance");
+    for vertices in [100, 400, 1600] {
+        let (points1, geometry1) = outline((0.0, 45.0), vertices);
+        let (points2, geometry2) = outline((8.0, 47.0), vertices);
+        let border1 = Border { points: &points1, geometry: &geometry1 };
+        let border2 = Border { points: &points2, geometry: &geometry2 };
+        group.bench_with_input(BenchmarkId::new("vertex scan", vertices), &vertices, |b, _| {
+            b.iter(|| calculate_min_distance_km(border1.points, border2.points, DistanceModel::GreatCircle))
+        });
+        group.bench_with_input(BenchmarkId::new("edge scan", vertices), &vertices, |b, _| {
+            b.iter(|| calculate_edge_distance_km(border1, border2, DistanceModel::GreatCircle))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, border_distance);
+criterion_main!(benches);
@@ -0,0 +1,470 @@
+//! The distance cache and its `--cache-file` format.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::path::Path;
+use geo::prelude::*;
+use anyhow::{Result, Context};
+use crate::{Config, CountryData};
+use untitled3::distance::DistanceModel;
+
+type PairKey = (DistanceModel, String, String);
+
+/// How distances are stored in the cache. Lower precision trades accuracy for
+/// memory when many pairs are cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CachePrecision {
+    /// Full `f64` precision.
+    Full,
+    /// `f32`, roughly 7 significant digits (well under a metre at Earth scale).
+    Single,
+    /// Whole kilometres as `u16`, accurate to ±0.5 km for distances up to 65535 km.
+    Kilometres,
+}
+
+impl std::str::FromStr for CachePrecision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "f64" | "full" => Ok(Self::Full),
+            "f32" | "single" => Ok(Self::Single),
+            "km" | "u16" => Ok(Self::Kilometres),
+            _ => anyhow::bail!("Unknown cache precision '{}'. Use f64, f32 or km", s),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CacheStore {
+    Full(HashMap<PairKey, f64>),
+    Single(HashMap<PairKey, f32>),
+    Kilometres(HashMap<PairKey, u16>),
+}
+
+impl CacheStore {
+    pub(crate) fn get(&self, key: &PairKey) -> Option<f64> {
+        match self {
+            Self::Full(map) => map.get(key).copied(),
+            Self::Single(map) => map.get(key).map(|&d| f64::from(d)),
+            Self::Kilometres(map) => map.get(key).map(|&d| f64::from(d)),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: PairKey, distance: f64) {
+        match self {
+            Self::Full(map) => {
+                map.insert(key, distance);
+            }
+            Self::Single(map) => {
+                map.insert(key, distance as f32);
+            }
+            Self::Kilometres(map) => {
+                map.insert(key, distance.round().clamp(0.0, f64::from(u16::MAX)) as u16);
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Full(map) => map.len(),
+            Self::Single(map) => map.len(),
+            Self::Kilometres(map) => map.len(),
+        }
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &PairKey> + '_> {
+        match self {
+            Self::Full(map) => Box::new(map.keys()),
+            Self::Single(map) => Box::new(map.keys()),
+            Self::Kilometres(map) => Box::new(map.keys()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DistanceCache {
+    cache: CacheStore,
+    /// Cheap lower bounds for pairs whose exact distance was never needed.
+    lower_bounds: HashMap<PairKey, f64>,
+    /// Centroid stand-ins for pairs over `--max-pair-vertices`, kept apart
+    /// from the exact distances so they are never saved.
+    fallbacks: HashMap<PairKey, f64>,
+    /// How many of `fallbacks` were added since `take_new_fallbacks`.
+    new_fallbacks: usize,
+}
+
+/// The distance cache as written by `--cache-file`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedCache {
+    settings: CacheSettings,
+    entries: Vec<(PairKey, f64)>,
+}
+
+/// Everything besides the pair and model that decides a cached distance. A
+/// cache file is only reused when all of it matches.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CacheSettings {
+    pub(crate) vertex_only: bool,
+    pub(crate) simplify: Option<f64>,
+    pub(crate) max_pair_vertices: Option<usize>,
+    /// `data_fingerprint` of the countries the distances were measured on.
+    pub(crate) data_fingerprint: u64,
+}
+
+impl CacheSettings {
+    pub(crate) fn new(config: &Config, countries: &[CountryData]) -> Self {
+        Self {
+            vertex_only: config.vertex_only,
+            simplify: config.simplify,
+            max_pair_vertices: config.max_pair_vertices,
+            data_fingerprint: data_fingerprint(countries),
+        }
+    }
+
+    /// Why distances saved under `saved` don't apply under `self`, if they don't.
+    fn mismatch(&self, saved: &Self) -> Option<String> {
+        let with = |set: bool| if set { "with" } else { "without" };
+        if saved.vertex_only != self.vertex_only {
+            return Some(format!("it was saved {} --vertex-only", with(saved.vertex_only)));
+        }
+        if saved.simplify != self.simplify {
+            return Some(match saved.simplify {
+                Some(tolerance) => format!("it was saved with --simplify {}", tolerance),
+                None => "it was saved without --simplify".to_string(),
+            });
+        }
+        if saved.max_pair_vertices != self.max_pair_vertices {
+            return Some(match saved.max_pair_vertices {
+                Some(limit) => format!("it was saved with --max-pair-vertices {}", limit),
+                None => "it was saved without --max-pair-vertices".to_string(),
+            });
+        }
+        if saved.data_fingerprint != self.data_fingerprint {
+            return Some("it was saved for different country data".to_string());
+        }
+        None
+    }
+}
+
+/// A hash of every country's name and border, the same whatever order the
+/// countries are in (so `--shuffle-data` keeps it). FNV-1a, since the
+/// standard hasher may change between Rust releases.
+fn data_fingerprint(countries: &[CountryData]) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    countries.iter()
+        .map(|country| {
+            let coordinates = country.geometry.coords_iter().flat_map(|coord| [coord.x.to_bits(), coord.y.to_bits()]);
+            country.name.bytes()
+                .map(u64::from)
+                .chain(coordinates)
+                .fold(OFFSET, |hash, value| (hash ^ value).wrapping_mul(PRIME))
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+fn is_binary_cache(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("bin"))
+}
+
+/// Cache key for a pair in either order. Names are case-folded so a pair keys
+/// the same however its names are capitalised.
+fn pair_key(model: DistanceModel, country1: &str, country2: &str) -> PairKey {
+    let (country1, country2) = (country1.to_lowercase(), country2.to_lowercase());
+    if country1 < country2 {
+        (model, country1, country2)
+    } else {
+        (model, country2, country1)
+    }
+}
+
+impl DistanceCache {
+    pub(crate) fn new(precision: CachePrecision) -> Self {
+        let cache = match precision {
+            CachePrecision::Full => CacheStore::Full(HashMap::new()),
+            CachePrecision::Single => CacheStore::Single(HashMap::new()),
+            CachePrecision::Kilometres => CacheStore::Kilometres(HashMap::new()),
+        };
+        Self { cache, lower_bounds: HashMap::new(), fallbacks: HashMap::new(), new_fallbacks: 0 }
+    }
+
+    pub(crate) fn get_or_calculate<F>(
+        &mut self,
+        model: DistanceModel,
+        country1: &str,
+        country2: &str,
+        calc_fn: F,
+    ) -> Option<f64>
+    where
+        F: FnOnce() -> Option<f64>,
+    {
+        let key = pair_key(model, country1, country2);
+
+        if let Some(distance) = self.cache.get(&key) {
+            Some(distance)
+        } else {
+            let distance = calc_fn()?;
+            self.cache.insert(key.clone(), distance);
+            self.cache.get(&key)
+        }
+    }
+
+    pub(crate) fn contains(&self, model: DistanceModel, country1: &str, country2: &str) -> bool {
+        self.cache.get(&pair_key(model, country1, country2)).is_some()
+    }
+
+    pub(crate) fn lower_bound_or_calculate<F>(&mut self, model: DistanceModel, country1: &str, country2: &str, calc_fn: F) -> f64
+    where
+        F: FnOnce() -> f64,
+    {
+        *self.lower_bounds.entry(pair_key(model, country1, country2)).or_insert_with(calc_fn)
+    }
+
+    pub(crate) fn fallback_or_calculate<F>(&mut self, model: DistanceModel, country1: &str, country2: &str, calc_fn: F) -> Option<f64>
+    where
+        F: FnOnce() -> Option<f64>,
+    {
+        let key = pair_key(model, country1, country2);
+        if let Some(&distance) = self.fallbacks.get(&key) {
+            return Some(distance);
+        }
+        let distance = calc_fn()?;
+        self.fallbacks.insert(key, distance);
+        self.new_fallbacks += 1;
+        Some(distance)
+    }
+
+    /// Pairs measured between centroids, which `len` leaves out.
+    pub(crate) fn fallback_count(&self) -> usize {
+        self.fallbacks.len()
+    }
+
+    /// How many pairs fell back to centroids since the last call, so each
+    /// command can note them once rather than per pair.
+    pub(crate) fn take_new_fallbacks(&mut self) -> usize {
+        std::mem::take(&mut self.new_fallbacks)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Writes every cached distance to `path`, as bincode for a `.bin` file
+    /// and as JSON otherwise.
+    fn save(&self, path: &str, settings: CacheSettings) -> Result<()> {
+        let mut entries: Vec<(PairKey, f64)> = self.cache.keys()
+            .filter_map(|key| Some((key.clone(), self.cache.get(key)?)))
+            .collect();
+        entries.sort_by(|x, y| (&x.0 .1, &x.0 .2).cmp(&(&y.0 .1, &y.0 .2)));
+        let saved = SavedCache { settings, entries };
+
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path))?;
+        let writer = io::BufWriter::new(file);
+        if is_binary_cache(path) {
+            bincode::serialize_into(writer, &saved)?;
+        } else {
+            serde_json::to_writer(writer, &saved)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the distances saved in `path` and returns how many there were.
+    /// Files saved under different `settings` are refused, since their
+    /// distances were measured differently.
+    pub(crate) fn load(&mut self, path: &str, settings: CacheSettings) -> Result<usize> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+        let reader = BufReader::new(file);
+        let saved: SavedCache = if is_binary_cache(path) {
+            bincode::deserialize_from(reader).context("Invalid binary cache file")?
+        } else {
+            serde_json::from_reader(reader).context("Invalid JSON cache file")?
+        };
+        if let Some(mismatch) = settings.mismatch(&saved.settings) {
+            anyhow::bail!(mismatch);
+        }
+
+        let count = saved.entries.len();
+        for (key, distance) in saved.entries {
+            self.cache.insert(key, distance);
+        }
+        Ok(count)
+    }
+
+    /// Cached pairs sorted by name so listings are stable between runs.
+    fn sorted_entries(&self) -> Vec<(&str, &str, f64)> {
+        let mut entries: Vec<_> = self.cache.keys()
+            .filter_map(|key| Some((key.1.as_str(), key.2.as_str(), self.cache.get(key)?)))
+            .collect();
+        entries.sort_by(|x, y| (x.0, x.1).cmp(&(y.0, y.1)));
+        entries
+    }
+}
+
+/// Fills the cache from `--cache-file` if it exists; a missing file is just
+/// created on exit.
+pub(crate) fn load_cache(cache: &Mutex<DistanceCache>, config: &Config, countries: &[CountryData]) {
+    let Some(path) = &config.cache_file else {
+        return;
+    };
+    if !Path::new(path).exists() {
+        return;
+    }
+    let Ok(mut cache) = cache.lock() else {
+        return;
+    };
+    match cache.load(path, CacheSettings::new(config, countries)) {
+        Ok(count) => println!("Loaded {} cached distances from {}", count, path),
+        Err(e) => println!("Warning: not using cache file {}: {:#}", path, e),
+    }
+}
+
+pub(crate) fn save_cache(cache: &Mutex<DistanceCache>, config: &Config, countries: &[CountryData]) {
+    let Some(path) = &config.cache_file else {
+        return;
+    };
+    let Ok(cache) = cache.lock() else {
+        return;
+    };
+    if let Err(e) = cache.save(path, CacheSettings::new(config, countries)) {
+        println!("Warning: could not save cache to {}: {:#}", path, e);
+    }
+}
+
+pub(crate) fn print_cache_report(cache: &Mutex<DistanceCache>, list: bool, countries: &[CountryData], precision: usize) {
+    let Ok(cache) = cache.lock() else {
+        println!("Error: distance cache is unavailable");
+        return;
+    };
+
+    println!("Distance cache holds {} country pairs.", cache.len());
+    if cache.fallback_count() > 0 {
+        println!("Plus {} pairs measured between centroids for --max-pair-vertices, which are not saved.", cache.fallback_count());
+    }
+    if list {
+        // Keys are case-folded; show the names as the data spells them.
+        let display = |key: &str| {
+            countries.iter()
+                .find(|country| country.name.to_lowercase() == key)
+                .map_or_else(|| key.to_string(), |country| country.name.clone())
+        };
+        for (country1, country2, distance_km) in cache.sorted_entries() {
+            println!("- {} <-> {}: {:.*} km", display(country1), display(country2), precision, distance_km);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Solver;
+
+    #[test]
+    fn cache_counts_each_pair_once_across_queries() {
+        let solver = Solver::fixture();
+        solver.search("Alpha", "10000--10000");
+        assert_eq!(solver.cache.lock().unwrap().len(), 4);
+        // Beta to Alpha is already known from the first query.
+        solver.search("Beta", "10000--10000");
+        let cache = solver.cache.lock().unwrap();
+        assert_eq!(cache.len(), 7);
+        assert_eq!(cache.sorted_entries().len(), 7);
+    }
+
+    #[test]
+    fn reduced_precision_stays_within_its_rounding_error() {
+        let full = Solver::fixture();
+        let single = Solver::fixture().with_config(|config| config.cache_precision = CachePrecision::Single);
+        let kilometres = Solver::fixture().with_config(|config| config.cache_precision = CachePrecision::Kilometres);
+        for other in ["Beta", "Gamma", "Delta Islands", "Antipodia"] {
+            let exact = full.distance_km("Alpha", other);
+            // f32 keeps about 7 significant digits.
+            assert!((single.distance_km("Alpha", other) - exact).abs() <= exact * 1e-7, "{}", other);
+            assert!((kilometres.distance_km("Alpha", other) - exact).abs() <= 0.5, "{}", other);
+            assert_eq!(kilometres.distance_km("Alpha", other).fract(), 0.0);
+        }
+    }
+
+    #[test]
+    fn each_model_keeps_its_own_entries() {
+        let mut solver = Solver::fixture();
+        let great_circle = solver.distance_km("Alpha", "Delta Islands");
+        solver.config.model = DistanceModel::Rhumb;
+        let rhumb = solver.distance_km("Alpha", "Delta Islands");
+        let cache = solver.cache.lock().unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(DistanceModel::GreatCircle, "Alpha", "Delta Islands"));
+        assert!(cache.contains(DistanceModel::Rhumb, "Delta Islands", "Alpha"));
+        assert!(rhumb >= great_circle);
+    }
+
+    #[test]
+    fn the_binary_format_round_trips() {
+        let solver = Solver::fixture();
+        solver.search("Alpha", "10000--10000");
+        solver.search("Delta Islands", "10000--10000");
+        let cache = solver.cache.lock().unwrap();
+        let settings = CacheSettings::new(&solver.config, &solver.countries);
+
+        let directory = std::env::temp_dir().join(format!("globle-solver-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (binary, json) = (directory.join("distances.bin"), directory.join("distances.json"));
+        cache.save(binary.to_str().unwrap(), settings).unwrap();
+        cache.save(json.to_str().unwrap(), settings).unwrap();
+        let (binary_bytes, json_bytes) = (std::fs::read(&binary).unwrap(), std::fs::read(&json).unwrap());
+
+        let mut restored = DistanceCache::new(solver.config.cache_precision);
+        assert_eq!(restored.load(binary.to_str().unwrap(), settings).unwrap(), cache.len());
+        let mut from_json = DistanceCache::new(solver.config.cache_precision);
+        from_json.load(json.to_str().unwrap(), settings).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // Bincode keeps every bit; JSON text may lose the last one.
+        assert_eq!(restored.sorted_entries(), cache.sorted_entries());
+        for ((a1, b1, json_km), (a2, b2, km)) in from_json.sorted_entries().into_iter().zip(cache.sorted_entries()) {
+            assert_eq!((a1, b1), (a2, b2));
+            assert!((json_km - km).abs() <= km * 1e-15);
+        }
+        assert!(serde_json::from_slice::<serde_json::Value>(&binary_bytes).is_err());
+        assert!(serde_json::from_slice::<serde_json::Value>(&json_bytes).is_ok());
+    }
+
+    #[test]
+    fn differently_cased_names_share_a_cache_entry() {
+        let mut cache = DistanceCache::new(CachePrecision::Full);
+        let model = DistanceModel::GreatCircle;
+        assert_eq!(cache.get_or_calculate(model, "France", "Spain", || Some(500.0)), Some(500.0));
+        for (country1, country2) in [("spain", "FRANCE"), ("FRANCE", "spain"), ("France", "Spain")] {
+            let distance = cache.get_or_calculate(model, country1, country2, || panic!("{} / {} missed the cache", country1, country2));
+            assert_eq!(distance, Some(500.0));
+            assert!(cache.contains(model, country1, country2));
+        }
+        assert_eq!(cache.len(), 1);
+        assert_eq!(pair_key(model, "Spain", "france"), pair_key(model, "FRANCE", "spain"));
+    }
+
+    #[test]
+    fn a_file_saved_under_other_settings_is_refused() {
+        let solver = Solver::fixture();
+        solver.search("Alpha", "10000--10000");
+        let settings = CacheSettings::new(&solver.config, &solver.countries);
+        let directory = std::env::temp_dir().join(format!("globle-solver-cache-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("distances.json");
+        let path = path.to_str().unwrap();
+        solver.cache.lock().unwrap().save(path, settings).unwrap();
+
+        let load = |settings: CacheSettings| DistanceCache::new(CachePrecision::Full).load(path, settings).map_err(|e| e.to_string());
+        assert_eq!(load(settings), Ok(4));
+        assert_eq!(
+            load(CacheSettings { max_pair_vertices: Some(100), ..settings }),
+            Err("it was saved without --max-pair-vertices".to_string()),
+        );
+        assert_eq!(load(CacheSettings { vertex_only: true, ..settings }), Err("it was saved without --vertex-only".to_string()));
+        let other_data = CacheSettings::new(&solver.config, &solver.countries[1..]);
+        assert_eq!(load(other_data), Err("it was saved for different country data".to_string()));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,1237 @@
+//! The REPL's commands besides a guess: each is parsed and answered here, from
+//! the one `Commands::run` dispatch.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use geo::{prelude::*, Point};
+use anyhow::{Result, Context};
+use crate::{Candidate, Config, CountryData, SearchProgress, SplitMix64, cached_distance_km, command_args, compared_models, find_country, find_mystery_countries, is_special_case, read_input};
+use crate::cache::{DistanceCache, print_cache_report};
+use untitled3::distance::{DistanceModel, Ellipsoid, Hull, calculate_min_distance_km, closest_border_points, great_circle_waypoints, hull_lower_bound_km, min_vertex_to_edge_km};
+use crate::export::export_candidates;
+use crate::query::{format_km, parse_query};
+use crate::session::{Session, replay_history, update_pool};
+
+/// What the REPL's commands read and keep between calls.
+pub(crate) struct Commands<'a> {
+    countries: &'a [CountryData],
+    cache: &'a Arc<Mutex<DistanceCache>>,
+    config: &'a Config,
+    profiles: Profiles,
+}
+
+impl<'a> Commands<'a> {
+    pub(crate) fn new(countries: &'a [CountryData], cache: &'a Arc<Mutex<DistanceCache>>, config: &'a Config) -> Self {
+        Commands { countries, cache, config, profiles: Profiles::default() }
+    }
+
+    /// Answers `line` if it is a command, returning whether it was; anything
+    /// else is left to be read as a guess.
+    pub(crate) fn run(&mut self, line: &str, session: &mut Session, input: &mut dyn BufRead, echo: bool) -> Result<bool> {
+        if line.eq_ignore_ascii_case("help") {
+            print_help();
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "cache") {
+            print_cache_report(self.cache, args.eq_ignore_ascii_case("list"), self.countries, self.config.precision);
+            return Ok(true);
+        }
+
+        if line.eq_ignore_ascii_case("new") {
+            session.reset_game();
+            println!("Started a new game; guessed countries stay excluded ('clear-guessed' to allow them).");
+            return Ok(true);
+        }
+
+        if line.eq_ignore_ascii_case("bisect") {
+            match session.remaining.as_deref() {
+                Some(remaining) if remaining.len() >= 2 => {
+                    print_bisect_suggestion(remaining, &session.guessed, self.countries, self.cache, self.config);
+                }
+                _ => println!("Bisect needs at least two remaining candidates; make a guess first."),
+            }
+            return Ok(true);
+        }
+
+        if line.eq_ignore_ascii_case("clear-guessed") {
+            session.guessed.clear();
+            session.invalidate_results();
+            println!("Previously guessed countries can be candidates again.");
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "distance") {
+            print_region_distance(args, self.countries, self.cache, self.config);
+            return Ok(true);
+        }
+
+        if line.eq_ignore_ascii_case("share") {
+            match session.share_token(self.config.model) {
+                Ok(token) => println!("Share token (restore with 'load <token>'):\n{}", token),
+                Err(e) => println!("Error creating share token: {:#}", e),
+            }
+            return Ok(true);
+        }
+
+        if let Some(history) = command_args(line, "replay") {
+            replay_history(history, session, self.countries, self.cache, self.config);
+            return Ok(true);
+        }
+
+        if let Some(token) = command_args(line, "load") {
+            match session.load_token(token) {
+                Ok(model) => {
+                    println!("Restored a game with {} guess(es).", session.constraints.len());
+                    if model != self.config.model {
+                        println!("Note: it was solved with a different distance model; restart with {} to match.", model.option());
+                    }
+                    if let Some(remaining) = &session.remaining {
+                        println!("Remaining ({}): {}", remaining.len(), remaining.join(", "));
+                    }
+                }
+                Err(e) => println!("Error loading token: {:#}", e),
+            }
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "confirm") {
+            print_confirmation(args, self.countries, self.cache, self.config, session);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "why-gone") {
+            println!("{}", explain_elimination(args, self.countries, self.cache, self.config, session));
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "profile") {
+            print_profile(args, &mut self.profiles, self.countries, self.cache, self.config);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "nearest") {
+            print_ranked_distances(args, false, &mut self.profiles, self.countries, self.cache, self.config);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "farthest") {
+            print_ranked_distances(args, true, &mut self.profiles, self.countries, self.cache, self.config);
+            return Ok(true);
+        }
+
+        if line.eq_ignore_ascii_case("calibrate") {
+            run_calibration(input, echo, self.countries, self.cache, self.config)?;
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "check-triangles") {
+            check_triangles(args, self.countries, self.cache, self.config);
+            return Ok(true);
+        }
+
+        if line.eq_ignore_ascii_case("closest-pair") {
+            println!("Scanning country pairs; this can take a while on a cold cache...");
+            match find_closest_pair(self.countries, self.cache, self.config) {
+                Some((a, b, distance_km)) => println!(
+                    "Closest countries that don't touch: {} and {}, {:.*} km apart",
+                    a.name,
+                    b.name,
+                    self.config.precision,
+                    distance_km,
+                ),
+                None => println!("No two countries are a nonzero distance apart."),
+            }
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "crossing") {
+            print_crossing(args, self.countries, self.config);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "path") {
+            print_path(args, self.countries);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "coords") {
+            print_coordinate_distances(args, self.countries, self.config);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "simulate-guess") {
+            print_hypothetical_guess(args, self.countries, self.cache, self.config, session);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "export") {
+            export_candidates(session, args);
+            return Ok(true);
+        }
+
+        if let Some(args) = command_args(line, "pool") {
+            update_pool(session, args, self.countries);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+fn print_help() {
+    println!("Commands:");
+    println!("  <country>      guess a country, then enter its distance");
+    println!("  cache [list]   show how many distances are cached (and list them)");
+    println!("  pool [A, B..]  only consider these candidates ('pool clear' to reset)");
+    println!("  pool from <file>  only consider the countries listed one per line in a file");
+    println!("  export <file>  write the remaining candidates one per line, for 'pool from'");
+    println!("  distance <country> to <region>  distance to the nearest country of a continent or region");
+    println!("  bisect         suggest a guess that splits the remaining candidates in half");
+    println!("  confirm <country>  the guess that best tells a suspected answer apart from the rest");
+    println!("  why-gone <country>  which guess ruled a country out, and by how much");
+    println!("  profile <country>  how far every other country is: neighbours, nearest, median, farthest");
+    println!("  nearest <country> [n]  the n countries closest to a country");
+    println!("  farthest <country> [n]  the n countries farthest from a country");
+    println!("  closest-pair   the two closest countries that don't touch");
+    println!("  calibrate      enter distances Globle showed to see which model matches it best");
+    println!("  check-triangles [n]  check n random triples of centroid distances for triangle inequality violations");
+    println!("  crossing <country> <country>  the closest point on each border and their distance");
+    println!("  path <country> to <country> [n]  n waypoints along the great circle between them");
+    println!("  coords <country> <lat,lon> ...  distance from a country to each coordinate");
+    println!("  simulate-guess <country> <distance>  how many candidates a guess would leave, without making it");
+    println!("  new            start over; guessed countries stay excluded until 'clear-guessed'");
+    println!("  share          print a token holding this game's guesses and settings");
+    println!("  load <token>   restore a game from a 'share' token");
+    println!("  replay <history>  start a game from guesses like 'France:1200; Spain:800--50'");
+    println!("  clear-guessed  allow previously guessed countries as candidates again");
+    println!("  help           show this list");
+    println!("  quit           exit");
+}
+
+/// Models `calibrate` checks the game's distances against.
+fn calibration_models(ellipsoid: Ellipsoid) -> [(DistanceModel, &'static str); 5] {
+    let [border, centroid, geodesic] = compared_models(ellipsoid);
+    [border, centroid, geodesic, (DistanceModel::Chord, "chord"), (DistanceModel::Rhumb, "rhumb")]
+}
+
+/// How far one model's distances sit from the game's over a set of pairs.
+#[derive(Debug, Clone, PartialEq)]
+struct ModelBias {
+    model: DistanceModel,
+    label: &'static str,
+    /// Mean of computed minus game distance; positive reads high.
+    mean_error_km: f64,
+    mean_abs_error_km: f64,
+}
+
+/// The bias of each model over `pairs` of countries with the distance the game
+/// showed, best match (lowest mean absolute error) first. Pairs a model can't
+/// measure are left out of its means; a model that measures none is dropped.
+fn model_biases<'a>(
+    pairs: &[(&'a CountryData, &'a CountryData, f64)],
+    models: &[(DistanceModel, &'static str)],
+    distance_km: impl Fn(DistanceModel, &CountryData, &CountryData) -> Option<f64>,
+) -> Vec<ModelBias> {
+    let mut biases: Vec<ModelBias> = models.iter()
+        .filter_map(|&(model, label)| {
+            let errors: Vec<f64> = pairs.iter()
+                .filter_map(|&(a, b, game_km)| Some(distance_km(model, a, b)? - game_km))
+                .collect();
+            if errors.is_empty() {
+                return None;
+            }
+            let count = errors.len() as f64;
+            Some(ModelBias {
+                model,
+                label,
+                mean_error_km: errors.iter().sum::<f64>() / count,
+                mean_abs_error_km: errors.iter().map(|error| error.abs()).sum::<f64>() / count,
+            })
+        })
+        .collect();
+    biases.sort_by(|a, b| a.mean_abs_error_km.total_cmp(&b.mean_abs_error_km));
+    biases
+}
+
+/// Reads one `calibrate` line, `<country> to <country>: <km>` or
+/// `<country>, <country>, <km>`, into the two countries and the distance.
+fn parse_calibration_pair<'a>(
+    line: &str,
+    countries: &'a [CountryData],
+) -> Option<(&'a CountryData, &'a CountryData, f64)> {
+    let (pair, distance) = line.rsplit_once([':', ','])?;
+    let distance_km: f64 = distance.trim().trim_end_matches("km").trim().parse().ok()?;
+    if !distance_km.is_finite() || distance_km < 0.0 {
+        return None;
+    }
+    let (a, b) = match pair.split_once(',') {
+        Some((first, second)) => (find_country(countries, first.trim())?, find_country(countries, second.trim())?),
+        None => split_country_pair(pair, countries)?,
+    };
+    Some((a, b, distance_km))
+}
+
+/// Handles `calibrate`: reads pairs of countries with the distance Globle
+/// showed between them, then reports how far each model reads from the game
+/// and which matches it best.
+fn run_calibration(
+    input: &mut dyn BufRead,
+    echo: bool,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) -> io::Result<()> {
+    println!("Enter pairs with the distance Globle showed, e.g. 'France to Spain: 0' or 'Chile, Japan, 16900'.");
+    let mut pairs = Vec::new();
+    loop {
+        print!("Pair {} (empty line to finish): ", pairs.len() + 1);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        let read = read_input(input, &mut line, echo)?;
+        let line = line.trim();
+        if read == 0 || line.is_empty() {
+            break;
+        }
+        match parse_calibration_pair(line, countries) {
+            Some((a, b, _)) if a.name == b.name => println!("Those are the same country; enter two different ones."),
+            Some(pair) => pairs.push(pair),
+            None => println!("Couldn't read that; use '<country> to <country>: <km>' with both in the database."),
+        }
+    }
+    if pairs.is_empty() {
+        println!("No pairs entered; nothing to calibrate.");
+        return Ok(());
+    }
+
+    let biases = model_biases(&pairs, &calibration_models(config.ellipsoid), |model, a, b| {
+        cached_distance_km(cache, a, b, &Config { model, ..config.clone() })
+    });
+    println!("\nOver {} pair(s), computed minus game distance:", pairs.len());
+    println!("  {:<9} {:>12} {:>12}", "model", "mean", "mean abs");
+    for bias in &biases {
+        println!(
+            "  {:<9} {:>12} {:>12}",
+            bias.label,
+            format!("{:+.*}", config.precision, bias.mean_error_km),
+            format!("{:.*}", config.precision, bias.mean_abs_error_km),
+        );
+    }
+    let Some(best) = biases.first() else {
+        println!("No model could measure these pairs.");
+        return Ok(());
+    };
+    let in_use = if best.model == config.model { ", the model in use" } else { "" };
+    println!(
+        "Best match: {} ({}{}), reading {} km {} on average.",
+        best.label,
+        best.model.option(),
+        in_use,
+        format_km(best.mean_error_km.abs(), config.precision),
+        if best.mean_error_km < 0.0 { "low" } else { "high" },
+    );
+    Ok(())
+}
+
+/// A guess whose distances to the remaining candidates split them into a near
+/// and a far half.
+#[derive(Debug)]
+struct Bisection<'a> {
+    guess: &'a str,
+    threshold_km: f64,
+    near: Vec<&'a str>,
+    far: Vec<&'a str>,
+}
+
+/// Finds the guess that divides `remaining` most evenly by distance, preferring
+/// the widest gap at the split so a slightly-off reading can't cross it.
+/// Countries in `guessed` are never suggested again.
+fn find_bisection<'a>(
+    remaining: &'a [String],
+    guessed: &[String],
+    countries: &'a [CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) -> Option<Bisection<'a>> {
+    let members: Vec<&CountryData> = remaining.iter()
+        .filter_map(|name| countries.iter().find(|c| &c.name == name))
+        .collect();
+
+    let mut best: Option<(usize, f64, Bisection)> = None;
+    for guess in countries.iter().filter(|guess| !guessed.contains(&guess.name)) {
+        let mut distances: Vec<(f64, &str)> = members.iter()
+            .filter_map(|member| {
+                let distance = if member.name == guess.name {
+                    0.0
+                } else {
+                    cached_distance_km(cache, guess, member, config)?
+                };
+                Some((distance, member.name.as_str()))
+            })
+            .collect();
+        if distances.len() < 2 {
+            continue;
+        }
+        distances.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+
+        // Split at the largest gap near the middle; with ties there may be no
+        // gap at the exact median.
+        let middle = distances.len() / 2;
+        let Some(split) = (1..distances.len())
+            .filter(|&i| distances[i].0 > distances[i - 1].0)
+            .min_by_key(|&i| i.abs_diff(middle))
+        else {
+            continue;
+        };
+        let imbalance = split.abs_diff(distances.len() - split);
+        let gap = distances[split].0 - distances[split - 1].0;
+
+        let better = best.as_ref().is_none_or(|(best_imbalance, best_gap, _)| {
+            imbalance < *best_imbalance || (imbalance == *best_imbalance && gap > *best_gap)
+        });
+        if better {
+            let bisection = Bisection {
+                guess: &guess.name,
+                threshold_km: (distances[split].0 + distances[split - 1].0) / 2.0,
+                near: distances[..split].iter().map(|d| d.1).collect(),
+                far: distances[split..].iter().map(|d| d.1).collect(),
+            };
+            best = Some((imbalance, gap, bisection));
+        }
+    }
+
+    best.map(|(_, _, bisection)| bisection)
+}
+
+/// The country of `region` nearest to `country`, with its distance.
+fn nearest_in_region<'a>(
+    country: &CountryData,
+    region: &str,
+    countries: &'a [CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) -> Option<(&'a CountryData, f64)> {
+    countries.iter()
+        .filter(|member| member.name != country.name && member.metadata.in_region(region))
+        .filter_map(|member| Some((member, cached_distance_km(cache, country, member, config)?)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Handles `distance <country> to <region>`.
+fn print_region_distance(args: &str, countries: &[CountryData], cache: &Mutex<DistanceCache>, config: &Config) {
+    let Some((name, region)) = args.rsplit_once(" to ").map(|(n, r)| (n.trim(), r.trim())) else {
+        println!("Usage: distance <country> to <continent or region>");
+        return;
+    };
+    let Some(country) = find_country(countries, name) else {
+        println!("Error: Country '{}' not found in database", name);
+        return;
+    };
+
+    match nearest_in_region(country, region, countries, cache, config) {
+        Some((nearest, distance_km)) => println!(
+            "{} to {}: {:.*} km (nearest: {})",
+            country.name,
+            region,
+            config.precision,
+            distance_km,
+            nearest.name,
+        ),
+        None => println!("No other countries found in region '{}'", region),
+    }
+}
+
+/// Handles `simulate-guess <country> <distance>`: how many candidates would be
+/// left if that guess came back with that distance. The session is untouched.
+fn print_hypothetical_guess(
+    args: &str,
+    countries: &[CountryData],
+    cache: &Arc<Mutex<DistanceCache>>,
+    config: &Config,
+    session: &Session,
+) {
+    let Some((name, distance)) = args.rsplit_once(char::is_whitespace).map(|(n, d)| (n.trim(), d)) else {
+        println!("Usage: simulate-guess <country> <distance>{}<margin>", config.delimiter);
+        return;
+    };
+    let Some(guess) = find_country(countries, name) else {
+        println!("Error: Country '{}' not found in database", name);
+        return;
+    };
+    let query = match parse_query(distance, config) {
+        Ok(query) => query,
+        Err(e) => {
+            println!("Error parsing distance: {}", e);
+            return;
+        }
+    };
+
+    let candidates = find_mystery_countries(
+        guess,
+        &query,
+        countries,
+        Arc::clone(cache),
+        config,
+        session,
+        &SearchProgress::default(),
+    );
+    let remaining = hypothetical_remaining(session, &guess.name, &candidates);
+    println!(
+        "If {} came back at {} km (margin {}), {} candidate(s) would remain: {}",
+        guess.name,
+        query.distance_km,
+        query.describe_margin(config.precision),
+        remaining.len(),
+        remaining.join(", "),
+    );
+}
+
+/// The candidates that would survive recording `candidates` for `guess`, the
+/// same narrowing `Session::record` and `Session::mark_guessed` apply.
+fn hypothetical_remaining<'a>(session: &'a Session, guess: &str, candidates: &'a [Candidate]) -> Vec<&'a str> {
+    let matched = |name: &str| name != guess && candidates.iter().any(|c| c.name == name);
+    match &session.remaining {
+        Some(remaining) => remaining.iter().map(String::as_str).filter(|name| matched(name)).collect(),
+        None => candidates.iter().map(|c| c.name.as_str()).filter(|name| matched(name)).collect(),
+    }
+}
+
+/// Handles `why-gone <country>`: the first guess of the game whose distance
+/// band ruled the country out, and how far outside it the country is.
+fn explain_elimination(
+    name: &str,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+    session: &Session,
+) -> String {
+    let Some(country) = find_country(countries, name) else {
+        return format!("Error: Country '{}' not found in database", name);
+    };
+    if session.guessed.contains(&country.name) {
+        return format!("{} was already guessed, so it can't be the answer.", country.name);
+    }
+    if !session.allows(country) {
+        return format!("{} is excluded by the candidate pool or the --landlocked/--coastal/--min-population filters.", country.name);
+    }
+
+    for constraint in &session.constraints {
+        let Some(guess) = countries.iter().find(|c| c.name == constraint.guess) else {
+            continue;
+        };
+        if !config.no_special && is_special_case(guess, country) {
+            continue;
+        }
+        let Some(distance_km) = cached_distance_km(cache, guess, country, config) else {
+            continue;
+        };
+        if constraint.query.contains(distance_km) {
+            continue;
+        }
+
+        let (side, off_km) = if distance_km < constraint.query.lower_bound() {
+            ("closer than", constraint.query.lower_bound() - distance_km)
+        } else {
+            ("farther than", distance_km - constraint.query.upper_bound())
+        };
+        return format!(
+            "{} was ruled out by {}: it is {:.*} km away, {:.*} km {} the band allowed.",
+            country.name,
+            constraint.describe(config.precision),
+            config.precision,
+            distance_km,
+            config.precision,
+            off_km,
+            side,
+        );
+    }
+
+    if session.constraints.is_empty() {
+        "No guesses yet in this game.".to_string()
+    } else {
+        format!("{} still fits every guess so far.", country.name)
+    }
+}
+
+/// Borders closer than this are taken to touch: neighbours' shared borders are
+/// not always digitised to line up exactly.
+const TOUCHING_KM: f64 = 1.0;
+
+/// The two distinct countries closest together without touching. Pairs are
+/// visited in order of a cheap lower bound on their distance, so the scan stops
+/// once no remaining pair could beat the best found.
+fn find_closest_pair<'a>(
+    countries: &'a [CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) -> Option<(&'a CountryData, &'a CountryData, f64)> {
+    let circle_bound = |a: &CountryData, b: &CountryData| match (&a.hull, &b.hull) {
+        (Some(Hull { circle: Some(c1), .. }), Some(Hull { circle: Some(c2), .. })) => c1.lower_bound_km(c2, config.model),
+        _ => 0.0,
+    };
+    let mut pairs: Vec<(f64, &CountryData, &CountryData)> = countries.iter()
+        .enumerate()
+        .flat_map(|(i, a)| countries[i + 1..].iter().map(move |b| (a, b)))
+        .map(|(a, b)| (circle_bound(a, b), a, b))
+        .collect();
+    pairs.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let mut best: Option<(&CountryData, &CountryData, f64)> = None;
+    for (bound_km, a, b) in pairs {
+        let best_km = best.map_or(f64::INFINITY, |(_, _, km)| km);
+        if bound_km >= best_km {
+            break;
+        }
+        if let (Some(hull1), Some(hull2)) = (&a.hull, &b.hull) {
+            if hull_lower_bound_km(hull1, hull2, config.model.base()) >= best_km {
+                continue;
+            }
+        }
+        let Some(distance_km) = cached_distance_km(cache, a, b, config) else {
+            continue;
+        };
+        if distance_km >= TOUCHING_KM && distance_km < best_km {
+            best = Some((a, b, distance_km));
+        }
+    }
+    best
+}
+
+/// Two countries named one after the other, split at `to` if given, or else at
+/// the first word boundary where both halves name a country.
+fn split_country_pair<'a>(args: &str, countries: &'a [CountryData]) -> Option<(&'a CountryData, &'a CountryData)> {
+    if let Some((first, second)) = args.split_once(" to ") {
+        return Some((find_country(countries, first.trim())?, find_country(countries, second.trim())?));
+    }
+    let words: Vec<&str> = args.split_whitespace().collect();
+    (1..words.len()).find_map(|split| {
+        let first = find_country(countries, &words[..split].join(" "))?;
+        let second = find_country(countries, &words[split..].join(" "))?;
+        Some((first, second))
+    })
+}
+
+/// Every other country's distance from a country, nearest first, kept per
+/// model so repeated `profile`, `nearest` and `farthest` commands reuse the
+/// sorted list instead of rebuilding it.
+#[derive(Default)]
+struct Profiles {
+    sorted: HashMap<(DistanceModel, String), Vec<Candidate>>,
+}
+
+impl Profiles {
+    fn get(
+        &mut self,
+        country: &CountryData,
+        countries: &[CountryData],
+        cache: &Mutex<DistanceCache>,
+        config: &Config,
+    ) -> &[Candidate] {
+        self.sorted.entry((config.model, country.name.clone())).or_insert_with(|| {
+            let mut distances: Vec<Candidate> = countries.iter()
+                .filter(|other| other.name != country.name)
+                .filter_map(|other| {
+                    let distance_km = cached_distance_km(cache, country, other, config)?;
+                    Some(Candidate { name: other.name.clone(), distance_km })
+                })
+                .collect();
+            distances.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km).then_with(|| a.name.cmp(&b.name)));
+            distances
+        })
+    }
+}
+
+/// Handles `profile <country>`: a summary of how far the other countries are.
+fn print_profile(
+    args: &str,
+    profiles: &mut Profiles,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) {
+    let Some(country) = find_country(countries, args) else {
+        println!("Usage: profile <country> (a country in the database)");
+        return;
+    };
+    let distances = profiles.get(country, countries, cache, config);
+    let (Some(first), Some(last)) = (distances.first(), distances.last()) else {
+        println!("{} has no other countries to compare with.", country.name);
+        return;
+    };
+    let touching: Vec<&str> = distances.iter()
+        .take_while(|candidate| candidate.distance_km < TOUCHING_KM)
+        .map(|candidate| candidate.name.as_str())
+        .collect();
+    let median = &distances[distances.len() / 2];
+
+    println!("Distances from {} to {} countries:", country.name, distances.len());
+    if touching.is_empty() {
+        println!("  Touches no other country");
+    } else {
+        println!("  Touches {}: {}", touching.len(), touching.join(", "));
+    }
+    if let Some(nearest) = distances.get(touching.len()) {
+        println!("  Nearest apart: {} at {:.*} km", nearest.name, config.precision, nearest.distance_km);
+    } else {
+        println!("  Nearest: {} at {:.*} km", first.name, config.precision, first.distance_km);
+    }
+    println!("  Median: {:.*} km ({})", config.precision, median.distance_km, median.name);
+    println!("  Farthest: {} at {:.*} km", last.name, config.precision, last.distance_km);
+}
+
+/// Handles `nearest <country> [n]` and `farthest <country> [n]`.
+fn print_ranked_distances(
+    args: &str,
+    farthest: bool,
+    profiles: &mut Profiles,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) {
+    const DEFAULT_COUNT: usize = 5;
+    let command = if farthest { "farthest" } else { "nearest" };
+    let (name, count) = match args.rsplit_once(char::is_whitespace) {
+        Some((name, count)) => match count.parse() {
+            Ok(count) => (name.trim(), count),
+            Err(_) => (args, DEFAULT_COUNT),
+        },
+        None => (args, DEFAULT_COUNT),
+    };
+    let Some(country) = find_country(countries, name) else {
+        println!("Usage: {} <country> [number of countries]", command);
+        return;
+    };
+    let distances = profiles.get(country, countries, cache, config);
+    let ranked: Box<dyn Iterator<Item = &Candidate>> = if farthest {
+        Box::new(distances.iter().rev())
+    } else {
+        Box::new(distances.iter())
+    };
+
+    let relation = if farthest { "farthest from" } else { "nearest to" };
+    println!("The {} countries {} {}:", count.min(distances.len()), relation, country.name);
+    for candidate in ranked.take(count) {
+        println!("- {} — {:.*} km", candidate.name, config.precision, candidate.distance_km);
+    }
+}
+
+/// Handles `check-triangles [samples]`: a sanity check of the distance code on
+/// random triples of countries, using centroid distances since those are true
+/// great-circle distances between points and must obey the triangle
+/// inequality.
+fn check_triangles(args: &str, countries: &[CountryData], cache: &Mutex<DistanceCache>, config: &Config) {
+    const DEFAULT_SAMPLES: usize = 1000;
+    let samples = if args.is_empty() { Ok(DEFAULT_SAMPLES) } else { args.parse() };
+    let Ok(samples) = samples else {
+        println!("Usage: check-triangles [number of triples]");
+        return;
+    };
+    let centroid = Config { model: DistanceModel::Centroid, ..config.clone() };
+    let seed = config.seed.unwrap_or_default();
+    let violations = triangle_violations(countries, samples, seed, |a, b| cached_distance_km(cache, a, b, &centroid));
+
+    if violations.is_empty() {
+        println!("No triangle inequality violations in {} random triples.", samples);
+    } else {
+        println!("{} of {} random triples violate the triangle inequality:", violations.len(), samples);
+        for violation in violations {
+            println!("- {}", violation);
+        }
+    }
+}
+
+/// Samples `samples` triples of distinct countries and describes every one
+/// where a side is longer than the other two together, beyond rounding.
+fn triangle_violations(
+    countries: &[CountryData],
+    samples: usize,
+    seed: u64,
+    distance_km: impl Fn(&CountryData, &CountryData) -> Option<f64>,
+) -> Vec<String> {
+    const TOLERANCE_KM: f64 = 1e-6;
+    if countries.len() < 3 {
+        return Vec::new();
+    }
+    let mut rng = SplitMix64::new(seed);
+    let mut violations = Vec::new();
+    for _ in 0..samples {
+        let a = &countries[rng.below(countries.len())];
+        let b = &countries[rng.below(countries.len())];
+        let c = &countries[rng.below(countries.len())];
+        if a.name == b.name || b.name == c.name || a.name == c.name {
+            continue;
+        }
+        let (Some(ab), Some(bc), Some(ac)) = (distance_km(a, b), distance_km(b, c), distance_km(a, c)) else {
+            continue;
+        };
+        if ac > ab + bc + TOLERANCE_KM {
+            violations.push(format!(
+                "{} to {} is {:.1} km, but via {} only {:.1} + {:.1} km",
+                a.name, c.name, ac, b.name, ab, bc,
+            ));
+        }
+    }
+    violations
+}
+
+/// Handles `crossing <country> <country>`: the point on each border closest to
+/// the other country, for checking adjacency or plotting.
+fn print_crossing(args: &str, countries: &[CountryData], config: &Config) {
+    let Some((first, second)) = split_country_pair(args, countries) else {
+        println!("Usage: crossing <country> <country> (both must be in the database)");
+        return;
+    };
+    let Some((a, b)) = closest_border_points(first.border(), second.border(), config.model.base()) else {
+        println!("{} and {} have no border points to compare", first.name, second.name);
+        return;
+    };
+    println!(
+        "Closest border points, {:.*} km apart (lat, lon):",
+        config.precision,
+        config.model.base().point_distance_m(&a, &b) / 1000.0,
+    );
+    println!("  {}: {:.4}, {:.4}", first.name, a.y(), a.x());
+    println!("  {}: {:.4}, {:.4}", second.name, b.y(), b.x());
+}
+
+/// Handles `path <guess> to <candidate> [count]`: waypoints along the great
+/// circle between the two countries' closest border points, for drawing on a map.
+fn print_path(args: &str, countries: &[CountryData]) {
+    const DEFAULT_WAYPOINTS: usize = 10;
+    let usage = "Usage: path <country> to <country> [number of waypoints]";
+    let Some((from_name, rest)) = args.split_once(" to ").map(|(f, r)| (f.trim(), r.trim())) else {
+        println!("{}", usage);
+        return;
+    };
+    let (to_name, count) = match rest.rsplit_once(char::is_whitespace) {
+        Some((name, count)) => match count.parse() {
+            Ok(count) => (name.trim(), count),
+            Err(_) => (rest, DEFAULT_WAYPOINTS),
+        },
+        None => (rest, DEFAULT_WAYPOINTS),
+    };
+    if count < 2 {
+        println!("A path needs at least 2 waypoints");
+        return;
+    }
+    let (Some(from), Some(to)) = (find_country(countries, from_name), find_country(countries, to_name)) else {
+        println!("Error: Country '{}' or '{}' not found in database", from_name, to_name);
+        return;
+    };
+    let Some((start, end)) = closest_border_points(from.border(), to.border(), DistanceModel::GreatCircle) else {
+        println!("{} and {} have no border points to join", from.name, to.name);
+        return;
+    };
+
+    if start.haversine_distance(&end) < 1.0 {
+        println!("{} and {} touch at {:.4}, {:.4}; there is no path between them.", from.name, to.name, start.y(), start.x());
+        return;
+    }
+
+    println!("Great-circle path from {} to {} ({} waypoints, lat, lon):", from.name, to.name, count);
+    for point in great_circle_waypoints(start, end, count) {
+        println!("{:.4}, {:.4}", point.y(), point.x());
+    }
+}
+
+/// Handles `coords <country> <lat,lon> [lat,lon ...]`: the distance from the
+/// country to each coordinate, 0 for coordinates inside it.
+fn print_coordinate_distances(args: &str, countries: &[CountryData], config: &Config) {
+    let usage = "Usage: coords <country> <lat,lon> [lat,lon ...]";
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let first_coordinate = words.iter().position(|word| parse_coordinate(word).is_ok());
+    let Some(first_coordinate) = first_coordinate.filter(|&i| i > 0) else {
+        println!("{}", usage);
+        return;
+    };
+    let coordinates = words.split_off(first_coordinate);
+    let name = words.join(" ");
+    let Some(country) = find_country(countries, &name) else {
+        println!("Error: Country '{}' not found in database", name);
+        return;
+    };
+
+    for text in coordinates {
+        match parse_coordinate(text) {
+            Ok(point) => {
+                let distance_km = coordinate_distance_km(country, point, config);
+                println!("{} to ({}, {}): {:.*} km", country.name, point.y(), point.x(), config.precision, distance_km);
+            }
+            Err(e) => println!("Skipping '{}': {}", text, e),
+        }
+    }
+}
+
+/// Parses `lat,lon` in degrees into a point (x is longitude).
+fn parse_coordinate(text: &str) -> Result<Point<f64>> {
+    let (lat, lon) = text.split_once(',').context("Use lat,lon, e.g. 48.85,2.35")?;
+    let lat: f64 = lat.trim().parse().context("Invalid latitude")?;
+    let lon: f64 = lon.trim().parse().context("Invalid longitude")?;
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        anyhow::bail!("Coordinate out of range");
+    }
+    Ok(Point::new(lon, lat))
+}
+
+fn coordinate_distance_km(country: &CountryData, point: Point<f64>, config: &Config) -> f64 {
+    if country.geometry.contains(&point) {
+        0.0
+    } else if config.vertex_only {
+        calculate_min_distance_km(&[point], &country.points, config.model)
+    } else {
+        min_vertex_to_edge_km(&[point], &country.geometry, config.model)
+    }
+}
+
+/// A guess that would tell a hypothesised answer apart from every other
+/// candidate, and the closest call it leaves.
+struct Confirmation<'a> {
+    guess: &'a CountryData,
+    answer_km: f64,
+    runner_up: &'a CountryData,
+    runner_up_km: f64,
+}
+
+/// The guess whose distance to `answer` sits farthest from its distance to any
+/// other candidate, so the game's reply would confirm or rule `answer` out.
+fn find_confirmation<'a>(
+    answer: &'a CountryData,
+    candidates: &[&'a CountryData],
+    countries: &'a [CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+    session: &Session,
+) -> Option<Confirmation<'a>> {
+    let mut best: Option<Confirmation> = None;
+    for guess in countries {
+        if guess.name == answer.name || session.guessed.contains(&guess.name) {
+            continue;
+        }
+        let Some(answer_km) = cached_distance_km(cache, guess, answer, config) else {
+            continue;
+        };
+        let runner_up = candidates.iter()
+            .filter(|c| c.name != answer.name && c.name != guess.name)
+            .filter_map(|c| Some((*c, cached_distance_km(cache, guess, c, config)?)))
+            .min_by(|a, b| (a.1 - answer_km).abs().total_cmp(&(b.1 - answer_km).abs()));
+        let Some((runner_up, runner_up_km)) = runner_up else {
+            continue;
+        };
+
+        let separation = |c: &Confirmation| (c.runner_up_km - c.answer_km).abs();
+        let confirmation = Confirmation { guess, answer_km, runner_up, runner_up_km };
+        if best.as_ref().is_none_or(|b| separation(&confirmation) > separation(b)) {
+            best = Some(confirmation);
+        }
+    }
+    best
+}
+
+/// Handles `confirm <country>`.
+fn print_confirmation(
+    name: &str,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+    session: &Session,
+) {
+    let Some(answer) = find_country(countries, name) else {
+        println!("Error: Country '{}' not found in database", name);
+        return;
+    };
+    let candidates: Vec<&CountryData> = match &session.remaining {
+        Some(remaining) => countries.iter().filter(|c| remaining.contains(&c.name)).collect(),
+        None => {
+            println!("No guesses yet, so every country is a candidate; this can take a while...");
+            countries.iter().filter(|c| session.allows(c)).collect()
+        }
+    };
+    if !candidates.iter().any(|c| c.name == answer.name) {
+        println!("Note: {} doesn't fit the guesses so far.", answer.name);
+    }
+
+    match find_confirmation(answer, &candidates, countries, cache, config, session) {
+        Some(c) => println!(
+            "Guess {} to confirm {}: it would be {:.*} km away, while the closest other candidate, {}, would be {:.*} km ({:.*} km apart).",
+            c.guess.name,
+            answer.name,
+            config.precision,
+            c.answer_km,
+            c.runner_up.name,
+            config.precision,
+            c.runner_up_km,
+            config.precision,
+            (c.runner_up_km - c.answer_km).abs(),
+        ),
+        None => println!("{} is the only candidate left; no guess is needed to confirm it.", answer.name),
+    }
+}
+
+fn print_bisect_suggestion(
+    remaining: &[String],
+    guessed: &[String],
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) {
+    match find_bisection(remaining, guessed, countries, cache, config) {
+        Some(bisection) => {
+            println!(
+                "Guess {}: under {:.*} km means one of {} ({}), otherwise one of {} ({}).",
+                bisection.guess,
+                config.precision,
+                bisection.threshold_km,
+                bisection.near.join(", "),
+                bisection.near.len(),
+                bisection.far.join(", "),
+                bisection.far.len(),
+            );
+        }
+        None => println!("No guess separates the remaining candidates."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{names, square, Solver};
+
+    #[test]
+    fn bisection_splits_the_remaining_candidates_evenly_without_repeating_a_guess() {
+        let solver = Solver::new(vec![
+            square("West", -20.0, 0.0, 1.0),
+            square("A", 0.0, 0.0, 1.0),
+            square("B", 4.0, 0.0, 1.0),
+            square("C", 10.0, 0.0, 1.0),
+            square("D", 14.0, 0.0, 1.0),
+        ]);
+        let remaining = ["A", "B", "C", "D"].map(String::from).to_vec();
+        let mut guessed = Vec::new();
+        for _ in 0..3 {
+            let bisection = find_bisection(&remaining, &guessed, &solver.countries, &solver.cache, &solver.config).unwrap();
+            assert!(!guessed.iter().any(|name| name == bisection.guess), "{} was guessed", bisection.guess);
+            assert_eq!((bisection.near.len(), bisection.far.len()), (2, 2), "{:?}", bisection);
+            for name in &bisection.near {
+                assert!(solver.distance_km(bisection.guess, name) < bisection.threshold_km);
+            }
+            for name in &bisection.far {
+                assert!(solver.distance_km(bisection.guess, name) > bisection.threshold_km);
+            }
+            guessed.push(bisection.guess.to_string());
+        }
+    }
+
+    #[test]
+    fn distance_to_a_region_is_the_minimum_over_its_members() {
+        let in_continent = |mut country: CountryData, continent: &str| {
+            country.metadata.continent = Some(continent.to_string());
+            country
+        };
+        let solver = Solver::new(vec![
+            in_continent(square("France", 0.0, 45.0, 2.0), "Europe"),
+            in_continent(square("Germany", 3.0, 45.0, 2.0), "Europe"),
+            in_continent(square("Turkey", 30.0, 38.0, 4.0), "Asia"),
+            in_continent(square("India", 75.0, 20.0, 5.0), "Asia"),
+            in_continent(square("Japan", 135.0, 35.0, 3.0), "Asia"),
+        ]);
+        let (nearest, distance_km) =
+            nearest_in_region(solver.country("France"), "asia", &solver.countries, &solver.cache, &solver.config).unwrap();
+        let minimum_km = ["Turkey", "India", "Japan"].iter()
+            .map(|member| solver.distance_km("France", member))
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!((nearest.name.as_str(), distance_km), ("Turkey", minimum_km));
+        // A country isn't its own region's nearest member.
+        let (nearest, _) =
+            nearest_in_region(solver.country("France"), "Europe", &solver.countries, &solver.cache, &solver.config).unwrap();
+        assert_eq!(nearest.name, "Germany");
+        assert!(nearest_in_region(solver.country("France"), "Oceania", &solver.countries, &solver.cache, &solver.config).is_none());
+    }
+
+    #[test]
+    fn a_hypothetical_guess_leaves_the_session_alone() {
+        let mut solver = Solver::fixture();
+        solver.guess("Alpha", "10000--10000");
+        let before = (solver.remaining(), solver.session.guessed.clone(), solver.session.constraints.len());
+
+        let candidates = solver.search("Beta", "0--200");
+        let hypothetical: Vec<String> = hypothetical_remaining(&solver.session, "Beta", &candidates)
+            .into_iter().map(String::from).collect();
+        assert_eq!(hypothetical, ["Gamma"]);
+        assert_eq!((solver.remaining(), solver.session.guessed.clone(), solver.session.constraints.len()), before);
+
+        // Making the guess for real leaves the same candidates.
+        solver.guess("Beta", "0--200");
+        assert_eq!(solver.remaining(), hypothetical);
+    }
+
+    #[test]
+    fn a_coordinate_on_the_border_is_at_no_distance() {
+        let solver = Solver::fixture();
+        let alpha = solver.country("Alpha");
+        let corner = alpha.points[0];
+        let on_edge = parse_coordinate(&format!("{},{}", corner.y(), corner.x() + 0.3)).unwrap();
+        for point in [corner, on_edge] {
+            assert!(coordinate_distance_km(alpha, point, &solver.config) < 1e-6, "{:?}", point);
+        }
+        let vertex_only = Config { vertex_only: true, ..solver.config.clone() };
+        assert!(coordinate_distance_km(alpha, corner, &vertex_only) < 1e-6);
+
+        // Beta is the fixture's 111.2 km east of Alpha, so its border is that far off.
+        let beta = solver.country("Beta");
+        let west_of_beta = Point::new(beta.geometry.bounding_rect().unwrap().min().x - 1.0, beta.points[0].y());
+        assert!((coordinate_distance_km(beta, west_of_beta, &solver.config) - 111.2).abs() < 0.1);
+        assert!(parse_coordinate("95,0").is_err() && parse_coordinate("0").is_err());
+    }
+
+    #[test]
+    fn why_gone_names_the_first_guess_that_failed() {
+        let mut solver = Solver::fixture();
+        solver.guess("Alpha", "10000--10000");
+        solver.guess("Beta", "800--50");
+        assert_eq!(solver.remaining(), ["Delta Islands"]);
+        let antipodia_km = solver.distance_km("Gamma", "Antipodia");
+        solver.guess("Gamma", &format!("{}--10", antipodia_km));
+        let why = |name: &str| explain_elimination(name, &solver.countries, &solver.cache, &solver.config, &solver.session);
+
+        assert_eq!(
+            why("antipodia"),
+            format!(
+                "Antipodia was ruled out by Beta at 800 km (margin 50 km): it is {:.1} km away, {:.1} km farther than the band allowed.",
+                solver.distance_km("Beta", "Antipodia"),
+                solver.distance_km("Beta", "Antipodia") - 850.0,
+            ),
+        );
+        // Delta Islands fit Beta's band and fail only the last guess.
+        let delta_km = solver.distance_km("Gamma", "Delta Islands");
+        assert_eq!(
+            why("Delta Islands"),
+            format!(
+                "Delta Islands was ruled out by {}: it is {:.1} km away, {:.1} km closer than the band allowed.",
+                solver.session.constraints[2].describe(1),
+                delta_km,
+                antipodia_km - 10.0 - delta_km,
+            ),
+        );
+        assert_eq!(why("Beta"), "Beta was already guessed, so it can't be the answer.");
+        assert_eq!(why("Narnia"), "Error: Country 'Narnia' not found in database");
+    }
+
+    #[test]
+    fn closest_pair_skips_touching_countries() {
+        let solver = Solver::fixture();
+        let (a, b, distance_km) = find_closest_pair(&solver.countries, &solver.cache, &solver.config).unwrap();
+        // Alpha and Gamma share a border. Beta is a degree east of both, its
+        // corner at (2, 1) equally near their shared corner at (1, 1).
+        assert_eq!((a.name.as_str(), b.name.as_str()), ("Alpha", "Beta"));
+        assert_eq!(distance_km, solver.distance_km("Beta", "Gamma"));
+        assert_eq!(solver.distance_km("Alpha", "Gamma"), 0.0);
+
+        let fresh = Solver::fixture();
+        let mut brute_force = Vec::new();
+        for (i, a) in fresh.countries.iter().enumerate() {
+            for b in &fresh.countries[i + 1..] {
+                brute_force.push(fresh.distance_km(&a.name, &b.name));
+            }
+        }
+        let expected_km = brute_force.into_iter().filter(|&km| km >= TOUCHING_KM).fold(f64::INFINITY, f64::min);
+        assert_eq!(distance_km, expected_km);
+    }
+
+    #[test]
+    fn confirm_recommends_the_guess_that_best_separates_the_answer() {
+        let mut solver = Solver::new(vec![
+            square("Answer", 0.0, 0.0, 1.0),
+            square("Northern", 0.0, 3.0, 1.0),
+            square("Eastern", 3.0, 0.0, 1.0),
+            square("Far East", 20.0, 0.0, 1.0),
+            square("South West", -6.0, -6.0, 1.0),
+        ]);
+        let candidates: Vec<&CountryData> = ["Answer", "Northern", "Eastern"].iter().map(|name| solver.country(name)).collect();
+        let best = find_confirmation(solver.country("Answer"), &candidates, &solver.countries, &solver.cache, &solver.config, &solver.session).unwrap();
+
+        let separation = |guess: &str| {
+            let answer_km = solver.distance_km(guess, "Answer");
+            ["Northern", "Eastern"].iter()
+                .filter(|&&other| other != guess)
+                .map(|other| (solver.distance_km(guess, other) - answer_km).abs())
+                .fold(f64::INFINITY, f64::min)
+        };
+        let best_separation = (best.runner_up_km - best.answer_km).abs();
+        assert_eq!(best_separation, separation(&best.guess.name));
+        for guess in ["Northern", "Eastern", "Far East", "South West"] {
+            assert!(separation(guess) <= best_separation, "{} separates better than {}", guess, best.guess.name);
+        }
+        assert!(best_separation > 50.0, "{}", best_separation);
+        let best_guess = best.guess.name.clone();
+
+        solver.session.mark_guessed(&best_guess);
+        let candidates: Vec<&CountryData> = ["Answer", "Northern", "Eastern"].iter().map(|name| solver.country(name)).collect();
+        let next = find_confirmation(solver.country("Answer"), &candidates, &solver.countries, &solver.cache, &solver.config, &solver.session).unwrap();
+        assert_ne!(next.guess.name, best_guess);
+    }
+
+    #[test]
+    fn a_second_profile_reuses_the_sorted_list() {
+        let solver = Solver::fixture();
+        let mut profiles = Profiles::default();
+        let first = profiles.get(solver.country("Alpha"), &solver.countries, &solver.cache, &solver.config).to_vec();
+        assert_eq!(names(&first), ["Gamma", "Beta", "Delta Islands", "Antipodia"]);
+
+        // With the distances forgotten, only the stored list can answer.
+        *solver.cache.lock().unwrap() = DistanceCache::new(solver.config.cache_precision);
+        let again = profiles.get(solver.country("Alpha"), &solver.countries, &solver.cache, &solver.config).to_vec();
+        assert_eq!(again, first);
+        assert_eq!(solver.cache.lock().unwrap().len(), 0);
+
+        // Another model needs its own list.
+        let centroid = Config { model: DistanceModel::Centroid, ..solver.config.clone() };
+        profiles.get(solver.country("Alpha"), &solver.countries, &solver.cache, &centroid);
+        assert!(solver.cache.lock().unwrap().len() > 0);
+    }
+
+    #[test]
+    fn a_broken_distance_function_fails_the_triangle_check() {
+        let solver = Solver::fixture();
+        let centroid = Config { model: DistanceModel::Centroid, ..solver.config.clone() };
+        let real = |a: &CountryData, b: &CountryData| cached_distance_km(&solver.cache, a, b, &centroid);
+        assert_eq!(triangle_violations(&solver.countries, 500, 1, real), Vec::<String>::new());
+
+        // Stretching one pair past any detour breaks the inequality.
+        let stretched = |a: &CountryData, b: &CountryData| {
+            let km = real(a, b)?;
+            let pair = [a.name.as_str(), b.name.as_str()];
+            Some(if pair.contains(&"Alpha") && pair.contains(&"Antipodia") { 3.0 * km } else { km })
+        };
+        let violations = triangle_violations(&solver.countries, 500, 1, stretched);
+        assert!(!violations.is_empty());
+        for violation in &violations {
+            assert!(violation.starts_with("Alpha to Antipodia") || violation.starts_with("Antipodia to Alpha"), "{}", violation);
+        }
+    }
+
+    #[test]
+    fn triples_matching_centroid_distances_recommend_the_centroid_model() {
+        let solver = Solver::fixture();
+        let km = |model: DistanceModel, a: &CountryData, b: &CountryData| {
+            cached_distance_km(&solver.cache, a, b, &Config { model, ..solver.config.clone() })
+        };
+        // What the game "showed": centroid distances, rounded as Globle rounds.
+        let pairs: Vec<(&CountryData, &CountryData, f64)> = [("Alpha", "Gamma"), ("Alpha", "Beta"), ("Beta", "Delta Islands"), ("Gamma", "Antipodia")]
+            .iter()
+            .map(|&(a, b)| {
+                let (a, b) = (solver.country(a), solver.country(b));
+                (a, b, km(DistanceModel::Centroid, a, b).unwrap().round())
+            })
+            .collect();
+        let biases = model_biases(&pairs, &calibration_models(solver.config.ellipsoid), km);
+        assert_eq!(biases[0].label, "centroid");
+        assert!(biases[0].mean_abs_error_km <= 0.5);
+        // Border distances read low, most of all for Gamma, which touches Alpha.
+        let border = biases.iter().find(|bias| bias.label == "border").unwrap();
+        assert!(border.mean_error_km < -10.0, "{:?}", border);
+
+        let (a, b, game_km) = parse_calibration_pair("Alpha to Gamma: 111 km", &solver.countries).unwrap();
+        assert_eq!((a.name.as_str(), b.name.as_str(), game_km), ("Alpha", "Gamma", 111.0));
+    }
+}
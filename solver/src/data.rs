@@ -0,0 +1,624 @@
+//! Loading, generating and checking country data.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::collections::HashMap;
+use std::path::Path;
+use serde::de::{Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use geo::{prelude::*, MultiPolygon, Point, Polygon};
+use geojson::{Feature, GeoJson, Value};
+use anyhow::{Result, Context};
+use crate::{CountryData, CountryMetadata, SplitMix64, half_present_special_pairs, strip_invisible};
+use untitled3::distance::Hull;
+
+/// Health check: loads and validates the dataset, prints one OK/FAIL line and
+/// exits with a matching status instead of starting the solver.
+pub(crate) fn check_data(path: &str, policy: UnknownGeometryPolicy) -> ! {
+    match data_health(path, policy) {
+        Ok(summary) => {
+            println!("OK: {}", summary);
+            std::process::exit(0);
+        }
+        Err(problems) => {
+            println!("FAIL: {}", problems);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The line `check_data` prints after OK or FAIL.
+fn data_health(path: &str, policy: UnknownGeometryPolicy) -> Result<String, String> {
+    let countries = load_country_data(path, policy).map_err(|e| format!("{:#}", e))?;
+    let problems = validate_countries(&countries);
+    if problems.is_empty() {
+        Ok(format!("{} countries loaded from {}", countries.len(), path))
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+/// Writes `count` made-up countries as a GeoJSON FeatureCollection, for timing
+/// the solver on datasets of a chosen size. Each is a star-shaped polygon of 8
+/// to 64 vertices around a random centre, so its ring never crosses itself.
+pub(crate) fn generate_dataset(count: usize, path: &str, seed: u64) -> Result<()> {
+    let mut rng = SplitMix64::new(seed);
+    let features = (0..count)
+        .map(|i| {
+            let (center_x, center_y) = (rng.between(-170.0, 170.0), rng.between(-60.0, 60.0));
+            let radius = rng.between(0.3, 5.0);
+            let vertices = 8 + rng.below(57);
+            let mut ring: Vec<Vec<f64>> = (0..vertices)
+                .map(|v| {
+                    let angle = std::f64::consts::TAU * v as f64 / vertices as f64;
+                    let r = radius * rng.between(0.5, 1.0);
+                    vec![center_x + r * angle.cos(), center_y + r * angle.sin()]
+                })
+                .collect();
+            ring.push(ring[0].clone());
+
+            let mut properties = geojson::JsonObject::new();
+            properties.insert("NAME".to_string(), format!("Country {}", i + 1).into());
+            properties.insert("POP_EST".to_string(), (rng.below(100_000_000) as f64).into());
+            Feature {
+                geometry: Some(geojson::Geometry::new(Value::Polygon(vec![ring]))),
+                properties: Some(properties),
+                ..Feature::default()
+            }
+        })
+        .collect();
+
+    let collection = geojson::FeatureCollection { bbox: None, features, foreign_members: None };
+    std::fs::write(path, collection.to_string()).with_context(|| format!("Failed to write {}", path))?;
+    println!("Wrote {} random countries to {} (seed {})", count, path, seed);
+    Ok(())
+}
+
+/// Length of a degree of latitude, the longest a degree gets anywhere.
+pub(crate) const KM_PER_DEGREE: f64 = 111.2;
+
+/// Run Douglas-Peucker over every border, returning the vertex counts before
+/// and after. Each border moves by at most `tolerance` degrees, so a distance
+/// between two countries moves by at most twice that.
+pub(crate) fn simplify_countries(countries: &mut [CountryData], tolerance: f64) -> (usize, usize) {
+    let mut before = 0;
+    let mut after = 0;
+    for country in countries {
+        before += country.geometry.coords_count();
+        country.geometry = country.geometry.simplify(&tolerance);
+        country.points = country.geometry.coords_iter().map(Point::from).collect();
+        country.hull = Hull::of(&country.geometry);
+        after += country.geometry.coords_count();
+    }
+    (before, after)
+}
+
+/// How far a border in the data may stray from the real one: half the median
+/// edge length, the most a real border between two vertices typically bulges
+/// away from the straight edge joining them.
+pub(crate) fn estimate_data_uncertainty_km(countries: &[CountryData]) -> f64 {
+    let mut lengths_km: Vec<f64> = countries.iter()
+        .flat_map(|country| country.geometry.lines_iter())
+        .map(|line| line.start_point().haversine_distance(&line.end_point()) / 1000.0)
+        .filter(|&length| length > 0.0)
+        .collect();
+    if lengths_km.is_empty() {
+        return 0.0;
+    }
+    let middle = lengths_km.len() / 2;
+    let (_, median, _) = lengths_km.select_nth_unstable_by(middle, f64::total_cmp);
+    *median / 2.0
+}
+
+/// Problems that would make the dataset unusable or its answers ambiguous.
+fn validate_countries(countries: &[CountryData]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if countries.is_empty() {
+        problems.push("no countries with a name and polygon geometry".to_string());
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    for country in countries {
+        if !seen.insert(country.name.to_lowercase()) {
+            problems.push(format!("duplicate country name '{}'", country.name));
+        }
+        if country.points.len() < 3 {
+            problems.push(format!("'{}' has fewer than 3 border points", country.name));
+        }
+
+        let out_of_range: Vec<&Point<f64>> = country.points.iter()
+            .filter(|p| !(-180.0..=180.0).contains(&p.x()) || !(-90.0..=90.0).contains(&p.y()))
+            .collect();
+        if let Some(first) = out_of_range.first() {
+            problems.push(format!(
+                "'{}' has {} coordinate(s) outside lon [-180, 180] / lat [-90, 90], first at ({}, {})",
+                country.name,
+                out_of_range.len(),
+                first.x(),
+                first.y(),
+            ));
+        }
+    }
+    problems
+}
+
+/// Prints every problem found in the dataset, one per line, and exits non-zero
+/// if there were any.
+pub(crate) fn validate_data(path: &str, policy: UnknownGeometryPolicy) -> ! {
+    let countries = match load_country_data(path, policy) {
+        Ok(countries) => countries,
+        Err(e) => {
+            println!("Could not load {}: {:#}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    // Not problems: a dataset may simply leave out a territory like Gibraltar.
+    for pair in half_present_special_pairs(&countries) {
+        println!("Note: enclave pair {} never applies", pair);
+    }
+    let problems = validate_countries(&countries);
+    if problems.is_empty() {
+        println!("{}: {} countries, no problems found", path, countries.len());
+        std::process::exit(0);
+    }
+    println!("{}: {} problem(s) found", path, problems.len());
+    for problem in &problems {
+        println!("- {}", problem);
+    }
+    std::process::exit(1);
+}
+
+/// Loads countries from a GeoJSON file, or from stdin when `path` is `-`: a
+/// FeatureCollection, or a single country from one Feature or a bare Geometry.
+pub(crate) fn load_country_data(path: &str, policy: UnknownGeometryPolicy) -> Result<Vec<CountryData>> {
+    if path == "-" {
+        return load_piped_country_data(io::stdin().lock(), policy);
+    }
+
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    if let Some(countries) = stream_country_data(BufReader::new(file), policy)? {
+        return Ok(countries);
+    }
+
+    // Not a FeatureCollection; such files hold one country and are small
+    // enough to parse whole.
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let geojson: GeoJson = serde_json::from_reader(BufReader::new(file))
+        .context("Invalid GeoJSON format")?;
+    let fallback_name = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(path);
+    let country = country_from_single(geojson, fallback_name)
+        .with_context(|| format!("{} does not contain a Polygon or MultiPolygon country", path))?;
+    Ok(vec![country])
+}
+
+/// Stdin can only be read once, so it is buffered whole to allow falling back
+/// to a single Feature or Geometry.
+fn load_piped_country_data<R: Read>(mut stdin: R, policy: UnknownGeometryPolicy) -> Result<Vec<CountryData>> {
+    let mut data = Vec::new();
+    stdin.read_to_end(&mut data).context("Failed to read country data from stdin")?;
+    if let Some(countries) = stream_country_data(data.as_slice(), policy)? {
+        return Ok(countries);
+    }
+
+    let geojson: GeoJson = serde_json::from_slice(&data).context("Invalid GeoJSON format")?;
+    let country = country_from_single(geojson, "stdin")
+        .context("stdin does not contain a Polygon or MultiPolygon country")?;
+    Ok(vec![country])
+}
+
+/// Builds the country held by a top-level Feature or Geometry. Bare geometries,
+/// and features without a `NAME`, take their name from the file.
+fn country_from_single(geojson: GeoJson, fallback_name: &str) -> Option<CountryData> {
+    let feature = match geojson {
+        GeoJson::Feature(feature) => feature,
+        GeoJson::Geometry(geometry) => Feature { geometry: Some(geometry), ..Feature::default() },
+        GeoJson::FeatureCollection(_) => return None,
+    };
+    country_from_feature(&feature)
+        .or_else(|| country_with_name(&feature, fallback_name.to_string()))
+}
+
+/// Parses a FeatureCollection one feature at a time, keeping only the extracted
+/// name and points so the raw geometry of the whole file is never held at once.
+/// Returns `None` if the top-level object is some other GeoJSON type.
+pub(crate) fn stream_country_data<R: Read>(reader: R, policy: UnknownGeometryPolicy) -> Result<Option<Vec<CountryData>>> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let collection = StreamedCollection::deserialize(&mut deserializer)
+        .context("Invalid GeoJSON format")?;
+    deserializer.end().context("Invalid GeoJSON format")?;
+    if collection.kind.as_deref() != Some("FeatureCollection") {
+        return Ok(None);
+    }
+
+    match policy {
+        UnknownGeometryPolicy::Skip => {}
+        UnknownGeometryPolicy::Warn => {
+            for feature in &collection.unsupported {
+                println!("Warning: skipping {}", feature);
+            }
+        }
+        UnknownGeometryPolicy::Error if !collection.unsupported.is_empty() => {
+            anyhow::bail!("Unsupported geometry: {}", collection.unsupported.join("; "));
+        }
+        UnknownGeometryPolicy::Error => {}
+    }
+    Ok(Some(collection.countries))
+}
+
+/// What to do with features whose geometry is neither a Polygon nor a
+/// MultiPolygon, which can't be used as countries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnknownGeometryPolicy {
+    Skip,
+    Warn,
+    Error,
+}
+
+impl std::str::FromStr for UnknownGeometryPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => anyhow::bail!("Unknown geometry policy '{}'. Use skip, warn or error", s),
+        }
+    }
+}
+
+struct StreamedCollection {
+    kind: Option<String>,
+    countries: Vec<CountryData>,
+    /// Descriptions of the features dropped for their geometry type.
+    unsupported: Vec<String>,
+}
+
+impl<'de> Deserialize<'de> for StreamedCollection {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CollectionVisitor;
+
+        impl<'de> Visitor<'de> for CollectionVisitor {
+            type Value = StreamedCollection;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a GeoJSON FeatureCollection")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut kind: Option<String> = None;
+                let mut countries = Vec::new();
+                let mut unsupported = Vec::new();
+
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "type" => kind = Some(map.next_value()?),
+                        "features" => {
+                            let features = map.next_value::<FeatureStream>()?;
+                            countries = features.countries;
+                            unsupported = features.unsupported;
+                        }
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(StreamedCollection { kind, countries, unsupported })
+            }
+        }
+
+        deserializer.deserialize_map(CollectionVisitor)
+    }
+}
+
+/// The `features` array, converted to `CountryData` as each element is read.
+struct FeatureStream {
+    countries: Vec<CountryData>,
+    unsupported: Vec<String>,
+}
+
+impl<'de> Deserialize<'de> for FeatureStream {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FeatureVisitor;
+
+        impl<'de> Visitor<'de> for FeatureVisitor {
+            type Value = FeatureStream;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of GeoJSON features")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut countries = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                let mut unsupported = Vec::new();
+                let mut index = 0;
+                while let Some(feature) = seq.next_element::<Feature>()? {
+                    match country_from_feature(&feature) {
+                        Some(country) => countries.push(country),
+                        None => unsupported.extend(describe_unsupported(&feature, index)),
+                    }
+                    index += 1;
+                }
+                Ok(FeatureStream { countries, unsupported })
+            }
+        }
+
+        deserializer.deserialize_seq(FeatureVisitor)
+    }
+}
+
+/// Names a feature dropped because its geometry is not a polygon; `None` for
+/// features dropped for other reasons, such as having no geometry.
+fn describe_unsupported(feature: &Feature, index: usize) -> Option<String> {
+    let geometry = feature.geometry.as_ref()?;
+    if matches!(geometry.value, Value::Polygon(_) | Value::MultiPolygon(_)) {
+        return None;
+    }
+    let name = feature.properties.as_ref()
+        .and_then(|properties| properties.get("NAME")?.as_str())
+        .map_or_else(|| format!("feature #{}", index + 1), |name| format!("'{}'", name));
+    Some(format!("{} with {} geometry", name, geometry.value.type_name()))
+}
+
+pub(crate) fn country_from_feature(country: &Feature) -> Option<CountryData> {
+    let name = country.properties.as_ref()?
+        .get("NAME")?
+        .as_str()?
+        .to_string();
+    country_with_name(country, name)
+}
+
+fn country_with_name(country: &Feature, name: String) -> Option<CountryData> {
+    let name = strip_invisible(&name).into_owned();
+    let points = extract_points(country)?;
+    let geometry = extract_geometry(country)?;
+    let hull = Hull::of(&geometry);
+    let metadata = CountryMetadata::from_feature(country);
+    Some(CountryData { source_name: name.clone(), name, points, geometry, hull, metadata })
+}
+
+/// Reads a JSON object such as `{"Czechia": "Czech Republic"}` mapping dataset
+/// names to the names the user prefers.
+pub(crate) fn load_name_translations(path: &str) -> Result<HashMap<String, String>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("{} is not a JSON object of name translations", path))
+}
+
+pub(crate) fn load_populations(path: &str) -> Result<HashMap<String, f64>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("{} is not a JSON object of populations", path))
+}
+
+/// Sets populations by dataset or display name, replacing any `POP_EST`.
+pub(crate) fn apply_populations(countries: &mut [CountryData], populations: &HashMap<String, f64>) {
+    for country in countries {
+        if let Some(&population) = populations.get(&country.source_name).or_else(|| populations.get(&country.name)) {
+            country.metadata.population = Some(population);
+        }
+    }
+}
+
+/// Renames countries for display and input; `source_name` keeps the dataset name.
+pub(crate) fn apply_name_translations(countries: &mut [CountryData], names: &HashMap<String, String>) {
+    for country in countries {
+        if let Some(preferred) = names.get(&country.source_name) {
+            country.name = strip_invisible(preferred).into_owned();
+        }
+    }
+}
+
+fn extract_points(country: &Feature) -> Option<Vec<Point<f64>>> {
+    let geometry = country.geometry.as_ref()?;
+    let mut points = Vec::with_capacity(100);
+
+    match &geometry.value {
+        Value::MultiPolygon(coords) => {
+            for polygon in coords {
+                for ring in polygon {
+                    points.extend(ring.iter().map(|coord| Point::new(coord[0], coord[1])));
+                }
+            }
+        }
+        Value::Polygon(coords) => {
+            for ring in coords {
+                points.extend(ring.iter().map(|coord| Point::new(coord[0], coord[1])));
+            }
+        }
+        _ => return None,
+    }
+    Some(points)
+}
+
+fn extract_geometry(country: &Feature) -> Option<MultiPolygon<f64>> {
+    match &country.geometry.as_ref()?.value {
+        value @ Value::MultiPolygon(_) => MultiPolygon::try_from(value).ok(),
+        value @ Value::Polygon(_) => Polygon::try_from(value).ok().map(|p| MultiPolygon::new(vec![p])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_country;
+    use crate::test_support::{country, fixture_countries, names as names_of, square, Solver, FIXTURE};
+
+    #[test]
+    fn streaming_parse_matches_bulk_parse() {
+        let streamed = stream_country_data(FIXTURE.as_bytes(), UnknownGeometryPolicy::Error).unwrap().unwrap();
+        let GeoJson::FeatureCollection(collection) = FIXTURE.parse::<GeoJson>().unwrap() else {
+            panic!("the fixture is a FeatureCollection");
+        };
+        let bulk: Vec<CountryData> = collection.features.iter().filter_map(country_from_feature).collect();
+
+        let summary = |countries: &[CountryData]| -> Vec<(String, Vec<Point<f64>>, MultiPolygon<f64>)> {
+            countries.iter().map(|c| (c.name.clone(), c.points.clone(), c.geometry.clone())).collect()
+        };
+        assert_eq!(streamed.len(), 5);
+        assert_eq!(summary(&streamed), summary(&bulk));
+    }
+
+    #[test]
+    fn renamed_countries_are_matched_and_shown_by_their_new_names() {
+        let mut countries = fixture_countries();
+        let names = HashMap::from([
+            ("Alpha".to_string(), "Alphaland".to_string()),
+            ("Delta Islands".to_string(), "The Deltas".to_string()),
+        ]);
+        apply_name_translations(&mut countries, &names);
+        let solver = Solver::new(countries);
+
+        assert_eq!(names_of(&solver.search("alphaland", "1000--50")), ["The Deltas"]);
+        assert_eq!(solver.country("The Deltas").source_name, "Delta Islands");
+        // The dataset's own name still finds it, shown by the new one.
+        assert_eq!(find_country(&solver.countries, "Alpha").unwrap().name, "Alphaland");
+    }
+
+    #[test]
+    fn a_single_feature_or_bare_geometry_loads_one_country() {
+        let directory = std::env::temp_dir().join(format!("globle-solver-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let ring = r#"[[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]]"#;
+        let feature = directory.join("feature.geojson");
+        std::fs::write(&feature, format!(
+            r#"{{"type": "Feature", "properties": {{"NAME": "Squareland"}}, "geometry": {{"type": "Polygon", "coordinates": {}}}}}"#,
+            ring,
+        )).unwrap();
+        let geometry = directory.join("Bare Island.geojson");
+        std::fs::write(&geometry, format!(r#"{{"type": "Polygon", "coordinates": {}}}"#, ring)).unwrap();
+
+        let from_feature = load_country_data(feature.to_str().unwrap(), UnknownGeometryPolicy::Error).unwrap();
+        let from_geometry = load_country_data(geometry.to_str().unwrap(), UnknownGeometryPolicy::Error).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(names_of_countries(&from_feature), ["Squareland"]);
+        // A bare geometry is named after its file.
+        assert_eq!(names_of_countries(&from_geometry), ["Bare Island"]);
+        assert_eq!(from_feature[0].geometry, from_geometry[0].geometry);
+    }
+
+    fn names_of_countries(countries: &[CountryData]) -> Vec<&str> {
+        countries.iter().map(|country| country.name.as_str()).collect()
+    }
+
+    #[test]
+    fn data_check_passes_the_fixture_and_fails_a_missing_file() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/synthetic_countries.json");
+        let summary = data_health(fixture, UnknownGeometryPolicy::Error).unwrap();
+        assert!(summary.starts_with("5 countries loaded"), "{}", summary);
+
+        let problems = data_health("no/such/countries.json", UnknownGeometryPolicy::Error).unwrap_err();
+        assert!(problems.contains("Failed to open no/such/countries.json"), "{}", problems);
+    }
+
+    #[test]
+    fn coordinates_off_the_globe_are_flagged() {
+        let countries = vec![
+            square("Fine", 179.0, 89.0, 1.0),
+            country("Misprojected", &[&[(10.0, 80.0), (12.0, 80.0), (12.0, 95.0), (10.0, 95.0), (10.0, 80.0)]]),
+        ];
+        let problems = validate_countries(&countries);
+        assert_eq!(problems, ["'Misprojected' has 2 coordinate(s) outside lon [-180, 180] / lat [-90, 90], first at (12, 95)"]);
+    }
+
+    #[test]
+    fn piped_data_loads_a_collection_or_a_single_country() {
+        let countries = load_piped_country_data(FIXTURE.as_bytes(), UnknownGeometryPolicy::Error).unwrap();
+        assert_eq!(names_of_countries(&countries), ["Alpha", "Beta", "Gamma", "Delta Islands", "Antipodia"]);
+
+        let geometry = r#"{"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]}"#;
+        let countries = load_piped_country_data(geometry.as_bytes(), UnknownGeometryPolicy::Error).unwrap();
+        assert_eq!(names_of_countries(&countries), ["stdin"]);
+        assert!(load_piped_country_data(&b"not json"[..], UnknownGeometryPolicy::Error).is_err());
+    }
+
+    #[test]
+    fn each_unknown_geometry_policy_handles_a_point_feature() {
+        let data = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {"NAME": "Square"}, "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]}},
+            {"type": "Feature", "properties": {"NAME": "Capital"}, "geometry": {"type": "Point", "coordinates": [0.5, 0.5]}},
+            {"type": "Feature", "properties": {}, "geometry": {"type": "LineString", "coordinates": [[0, 0], [1, 1]]}}
+        ]}"#;
+        for policy in [UnknownGeometryPolicy::Skip, UnknownGeometryPolicy::Warn] {
+            let countries = stream_country_data(data.as_bytes(), policy).unwrap().unwrap();
+            assert_eq!(names_of_countries(&countries), ["Square"], "{:?}", policy);
+        }
+        let Err(error) = stream_country_data(data.as_bytes(), UnknownGeometryPolicy::Error) else {
+            panic!("the Point feature is an error");
+        };
+        assert_eq!(error.to_string(), "Unsupported geometry: 'Capital' with Point geometry; feature #3 with LineString geometry");
+
+        assert_eq!("WARN".parse::<UnknownGeometryPolicy>().unwrap(), UnknownGeometryPolicy::Warn);
+        assert!("ignore".parse::<UnknownGeometryPolicy>().is_err());
+    }
+
+    #[test]
+    fn the_data_uncertainty_widens_the_band_over_a_narrow_miss() {
+        // 0.2° squares: edges of about 22 km, so borders may be 11 km off.
+        let countries = vec![square("Home", 0.0, 0.0, 0.2), square("Other", 1.2, 0.0, 0.2)];
+        let uncertainty_km = estimate_data_uncertainty_km(&countries);
+        assert!((uncertainty_km - 0.1 * KM_PER_DEGREE).abs() < 0.1, "{}", uncertainty_km);
+
+        let raw = Solver::new(countries);
+        let gap_km = raw.distance_km("Home", "Other");
+        let narrow_miss = format!("{}--5", gap_km - 8.0);
+        assert!(raw.search("Home", &narrow_miss).is_empty());
+        let widened = Solver::new(raw.countries.clone()).with_config(|config| config.data_uncertainty_km = Some(uncertainty_km));
+        assert_eq!(widened.query(&narrow_miss).upper_margin_km, 5.0 + uncertainty_km);
+        assert_eq!(names_of(&widened.search("Home", &narrow_miss)), ["Other"]);
+    }
+
+    #[test]
+    fn simplifying_cuts_vertices_and_keeps_distances_within_the_tolerance() {
+        // A wobbly 720-vertex circle: plenty for Douglas-Peucker to drop.
+        let ring: Vec<(f64, f64)> = (0..=720)
+            .map(|i| (i % 720) as f64)
+            .map(|i| {
+                let angle = i * std::f64::consts::PI / 360.0;
+                let radius = 2.0 + 0.003 * (i * 7.0).sin();
+                (radius * angle.cos(), radius * angle.sin())
+            })
+            .collect();
+        let countries = vec![country("Round", &[&ring]), square("Neighbour", 3.0, -0.5, 1.0), square("Distant", 30.0, 20.0, 1.0)];
+        let tolerance = 0.01;
+        let mut simplified = countries.clone();
+        let (before, after) = simplify_countries(&mut simplified, tolerance);
+        assert_eq!(before, 721 + 5 + 5);
+        assert!(after < before / 4, "{} of {} vertices left", after, before);
+
+        let (exact, coarse) = (Solver::new(countries), Solver::new(simplified));
+        for other in ["Neighbour", "Distant"] {
+            let shift_km = (coarse.distance_km("Round", other) - exact.distance_km("Round", other)).abs();
+            assert!(shift_km <= 2.0 * tolerance * KM_PER_DEGREE, "{} moved {} km", other, shift_km);
+        }
+    }
+
+    #[test]
+    fn the_generated_dataset_loads_with_the_requested_size() {
+        let directory = std::env::temp_dir().join(format!("globle-solver-generated-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("random.geojson");
+        let path = path.to_str().unwrap();
+
+        generate_dataset(25, path, 3).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        let collection: geojson::FeatureCollection = contents.parse::<GeoJson>().unwrap().try_into().unwrap();
+        assert_eq!(collection.features.len(), 25);
+        let countries = load_country_data(path, UnknownGeometryPolicy::Error).unwrap();
+        assert_eq!(countries.len(), 25);
+        assert!(countries.iter().all(|country| country.metadata.population.is_some()));
+        assert_eq!((countries[0].name.as_str(), countries[24].name.as_str()), ("Country 1", "Country 25"));
+
+        // The seed fixes the shapes.
+        generate_dataset(25, path, 3).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), contents);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
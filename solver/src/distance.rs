@@ -0,0 +1,548 @@
+//! Distance models and the border-to-border distance computations.
+
+use geo::{prelude::*, Closest, Coord, Line, MultiPolygon, Point, Polygon};
+use anyhow::Result;
+
+/// Radius geo's haversine distance uses, so the models stay comparable.
+const MEAN_EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// How the distance between two points on the Earth is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum DistanceModel {
+    /// Shortest path over the surface (haversine), as the game uses.
+    GreatCircle,
+    /// Constant-bearing path (loxodrome), what a compass course would follow.
+    Rhumb,
+    /// Straight line through the Earth between the two surface points.
+    Chord,
+    /// Shortest path over an ellipsoid (WGS84 unless overridden), within
+    /// about 0.5% of the great-circle distance but slower to compute.
+    Geodesic(Ellipsoid),
+    /// Great-circle distance between the countries' centroids.
+    Centroid,
+    /// Weighted mix of the great-circle border distance and the centroid
+    /// distance. The weight is kept in thousandths so the model stays hashable.
+    Blend { border_weight_permille: u16 },
+}
+
+impl DistanceModel {
+    /// Blend with `border_weight` (0 to 1) given to the border distance.
+    pub fn blend(border_weight: f64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&border_weight) {
+            anyhow::bail!("Blend weight must be between 0 and 1, got {}", border_weight);
+        }
+        Ok(Self::Blend { border_weight_permille: (border_weight * 1000.0).round() as u16 })
+    }
+
+    /// The point-to-point metric the model is built on. Centroid and blended
+    /// distances are both measured along great circles.
+    pub fn base(self) -> Self {
+        match self {
+            Self::Rhumb => Self::Rhumb,
+            Self::Chord => Self::Chord,
+            Self::Geodesic(ellipsoid) => Self::Geodesic(ellipsoid),
+            _ => Self::GreatCircle,
+        }
+    }
+
+    /// The command-line option that selects this model.
+    pub fn option(self) -> String {
+        match self {
+            Self::GreatCircle => "--model great-circle".to_string(),
+            Self::Rhumb => "--model rhumb".to_string(),
+            Self::Chord => "--model chord".to_string(),
+            Self::Geodesic(ellipsoid) if ellipsoid == Ellipsoid::WGS84 => "--model geodesic".to_string(),
+            Self::Geodesic(ellipsoid) => format!(
+                "--model geodesic --semi-major {} --flattening {}",
+                ellipsoid.semi_major_m(),
+                ellipsoid.flattening(),
+            ),
+            Self::Centroid => "--model centroid".to_string(),
+            Self::Blend { border_weight_permille } => {
+                format!("--blend {}", f64::from(border_weight_permille) / 1000.0)
+            }
+        }
+    }
+
+    /// What is measured, for the header, e.g. "nearest-border great-circle
+    /// distance".
+    pub fn describe(self, vertex_only: bool) -> String {
+        let between = if vertex_only { "nearest-vertex" } else { "nearest-border" };
+        match self {
+            Self::GreatCircle => format!("{} great-circle distance", between),
+            Self::Rhumb => format!("{} rhumb-line distance", between),
+            Self::Chord => format!("{} straight-line distance through the Earth", between),
+            Self::Geodesic(ellipsoid) => format!("{} geodesic distance on {}", between, ellipsoid.describe()),
+            Self::Centroid => "great-circle distance between centroids".to_string(),
+            Self::Blend { border_weight_permille } => format!(
+                "{:.0}% {} great-circle distance, {:.0}% centroid distance",
+                f64::from(border_weight_permille) / 10.0,
+                between,
+                100.0 - f64::from(border_weight_permille) / 10.0,
+            ),
+        }
+    }
+
+    /// How the model relates to the distances Globle shows.
+    pub fn game_note(self) -> &'static str {
+        match self {
+            Self::GreatCircle => " (what Globle shows; change with --model)",
+            _ => " (Globle uses nearest-border great-circle distance)",
+        }
+    }
+
+    /// The farthest apart two points can be, where the model has such a limit.
+    /// Rhumb lines between near-antipodal points can wind much further.
+    pub fn max_distance_km(self) -> Option<f64> {
+        let radius_km = MEAN_EARTH_RADIUS_M / 1000.0;
+        match self.base() {
+            Self::Rhumb => None,
+            Self::Chord => Some(2.0 * radius_km),
+            Self::Geodesic(ellipsoid) => Some(ellipsoid.half_meridian_km()),
+            _ => Some(std::f64::consts::PI * radius_km),
+        }
+    }
+
+    pub fn point_distance_m(self, p1: &Point<f64>, p2: &Point<f64>) -> f64 {
+        match self.base() {
+            Self::Rhumb => rhumb_distance_m(p1, p2),
+            Self::Chord => chord_distance_m(p1, p2),
+            Self::Geodesic(ellipsoid) => ellipsoid.distance_m(p1, p2),
+            _ => p1.haversine_distance(p2),
+        }
+    }
+}
+
+impl std::str::FromStr for DistanceModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "great-circle" | "haversine" => Ok(Self::GreatCircle),
+            "rhumb" | "loxodrome" => Ok(Self::Rhumb),
+            "chord" => Ok(Self::Chord),
+            "geodesic" | "ellipsoid" => Ok(Self::Geodesic(Ellipsoid::WGS84)),
+            "centroid" => Ok(Self::Centroid),
+            _ => anyhow::bail!("Unknown distance model '{}'. Use great-circle, rhumb, chord, geodesic or centroid", s),
+        }
+    }
+}
+
+/// The ellipsoid geodesic distances are measured on, kept as the bit patterns
+/// of its semi-major axis (metres) and flattening so the model stays hashable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Ellipsoid {
+    pub semi_major_bits: u64,
+    pub flattening_bits: u64,
+}
+
+impl Ellipsoid {
+    pub const WGS84: Self = Self {
+        semi_major_bits: 6_378_137.0f64.to_bits(),
+        flattening_bits: (1.0 / 298.257_223_563f64).to_bits(),
+    };
+
+    pub fn new(semi_major_m: f64, flattening: f64) -> Result<Self> {
+        if !(semi_major_m > 0.0 && semi_major_m.is_finite()) {
+            anyhow::bail!("The semi-major axis must be a positive number of metres, got {}", semi_major_m);
+        }
+        if !(-1.0..1.0).contains(&flattening) {
+            anyhow::bail!("Flattening must be between -1 and 1, got {}", flattening);
+        }
+        Ok(Self { semi_major_bits: semi_major_m.to_bits(), flattening_bits: flattening.to_bits() })
+    }
+
+    pub fn semi_major_m(self) -> f64 {
+        f64::from_bits(self.semi_major_bits)
+    }
+
+    pub fn flattening(self) -> f64 {
+        f64::from_bits(self.flattening_bits)
+    }
+
+    pub fn describe(self) -> String {
+        if self == Self::WGS84 {
+            "the WGS84 ellipsoid".to_string()
+        } else {
+            format!("an ellipsoid with a = {} m, f = {}", self.semi_major_m(), self.flattening())
+        }
+    }
+
+    fn distance_m(self, p1: &Point<f64>, p2: &Point<f64>) -> f64 {
+        use geographiclib_rs::InverseGeodesic;
+        let geodesic = geographiclib_rs::Geodesic::new(self.semi_major_m(), self.flattening());
+        geodesic.inverse(p1.y(), p1.x(), p2.y(), p2.x())
+    }
+
+    /// Approximately half a meridian, the longest geodesic on an oblate
+    /// ellipsoid.
+    fn half_meridian_km(self) -> f64 {
+        let semi_minor_m = self.semi_major_m() * (1.0 - self.flattening());
+        std::f64::consts::PI * (self.semi_major_m() + semi_minor_m) / 2.0 / 1000.0
+    }
+
+    /// The least a geodesic can be relative to the great-circle distance over
+    /// the mean sphere: the smallest radius of curvature anywhere on the
+    /// ellipsoid over the sphere's radius. For WGS84 that is north-south at
+    /// the equator, 6335 km against 6371 km.
+    fn min_ratio(self) -> f64 {
+        let a = self.semi_major_m();
+        let b = a * (1.0 - self.flattening());
+        [b * b / a, a, a * a / b].into_iter().fold(f64::INFINITY, f64::min) / MEAN_EARTH_RADIUS_M
+    }
+}
+
+/// Straight-line distance through the sphere: the chord under the great-circle
+/// arc, up to the full diameter for antipodal points.
+fn chord_distance_m(p1: &Point<f64>, p2: &Point<f64>) -> f64 {
+    let central_angle = p1.haversine_distance(p2) / MEAN_EARTH_RADIUS_M;
+    2.0 * MEAN_EARTH_RADIUS_M * (central_angle / 2.0).sin()
+}
+
+/// Rhumb-line distance on a sphere of the same radius `haversine_distance` uses.
+fn rhumb_distance_m(p1: &Point<f64>, p2: &Point<f64>) -> f64 {
+    use std::f64::consts::{FRAC_PI_4, PI};
+
+    let (lat1, lat2) = (p1.y().to_radians(), p2.y().to_radians());
+    let delta_lat = lat2 - lat1;
+    let mut delta_lon = (p2.x() - p1.x()).to_radians();
+    if delta_lon.abs() > PI {
+        delta_lon -= delta_lon.signum() * 2.0 * PI;
+    }
+
+    // Stretched latitude difference on a Mercator projection; along a parallel it
+    // vanishes and the east-west scale is just cos(latitude).
+    let delta_psi = ((lat2 / 2.0 + FRAC_PI_4).tan() / (lat1 / 2.0 + FRAC_PI_4).tan()).ln();
+    let q = if delta_psi.abs() > 1e-12 { delta_lat / delta_psi } else { lat1.cos() };
+
+    (delta_lat * delta_lat + q * q * delta_lon * delta_lon).sqrt() * MEAN_EARTH_RADIUS_M
+}
+
+/// A country's convex hull, densified so its vertices sample the whole outline.
+#[derive(Clone)]
+pub struct Hull {
+    pub polygon: Polygon<f64>,
+    pub points: Vec<Point<f64>>,
+    /// Half the longest gap between consecutive hull points: every point of the
+    /// outline lies within this distance of some hull point.
+    pub half_gap_km: f64,
+    /// Spherical cap holding the whole hull, for a test cheaper than the hull's.
+    pub circle: Option<BoundingCircle>,
+}
+
+/// A circle on the sphere containing every point of a country.
+#[derive(Clone)]
+pub struct BoundingCircle {
+    pub center: Point<f64>,
+    pub radius_km: f64,
+}
+
+impl BoundingCircle {
+    /// Centred on the normalised mean of the hull samples, with the radius
+    /// reaching the farthest sample plus the gap an unsampled point may sit in.
+    /// Caps of a quarter turn or more are not convex and give no circle.
+    pub fn of(points: &[Point<f64>], half_gap_km: f64) -> Option<Self> {
+        let (x, y, z) = points.iter().fold((0.0, 0.0, 0.0), |(x, y, z), p| {
+            let (lat, lon) = (p.y().to_radians(), p.x().to_radians());
+            (x + lat.cos() * lon.cos(), y + lat.cos() * lon.sin(), z + lat.sin())
+        });
+        let norm = (x * x + y * y + z * z).sqrt();
+        if norm < 1e-9 {
+            return None;
+        }
+        let center = Point::new(y.atan2(x).to_degrees(), (z / norm).asin().to_degrees());
+        let radius_km = points.iter()
+            .map(|p| center.haversine_distance(p) / 1000.0)
+            .fold(0.0, f64::max) + half_gap_km;
+        let quarter_turn_km = std::f64::consts::FRAC_PI_2 * MEAN_EARTH_RADIUS_M / 1000.0;
+        (radius_km < quarter_turn_km).then_some(Self { center, radius_km })
+    }
+
+    /// A distance no greater than that between any point of the two circles,
+    /// measured with `model`.
+    pub fn lower_bound_km(&self, other: &Self, model: DistanceModel) -> f64 {
+        let arc_km = (self.center.haversine_distance(&other.center) / 1000.0 - self.radius_km - other.radius_km).max(0.0);
+        match model.base() {
+            // Chords only shrink relative to their arcs, by a monotonic factor.
+            DistanceModel::Chord => {
+                let radius_km = MEAN_EARTH_RADIUS_M / 1000.0;
+                2.0 * radius_km * (arc_km / (2.0 * radius_km)).sin()
+            }
+            DistanceModel::Geodesic(ellipsoid) => arc_km * ellipsoid.min_ratio(),
+            // Rhumb lines are never shorter than great circles.
+            _ => arc_km,
+        }
+    }
+}
+
+/// Hull edges are split into pieces no longer than this many degrees.
+const HULL_STEP_DEGREES: f64 = 0.5;
+
+impl Hull {
+    /// Countries spanning more than half the globe in longitude cross the
+    /// antimeridian, and their planar hull would not contain them.
+    pub fn of(geometry: &MultiPolygon<f64>) -> Option<Self> {
+        let bounds = geometry.bounding_rect()?;
+        if bounds.width() > 180.0 {
+            return None;
+        }
+        let polygon = geometry.convex_hull();
+        let outline = polygon.exterior().densify(HULL_STEP_DEGREES);
+        let half_gap_km = outline.lines()
+            .map(|line| line.start_point().haversine_distance(&line.end_point()))
+            .fold(0.0, f64::max) / 2000.0;
+        let points: Vec<Point<f64>> = outline.points().collect();
+        // Points inside the hull lie on meridian arcs between outline points,
+        // so a convex cap around the outline holds them too.
+        let circle = BoundingCircle::of(&points, half_gap_km);
+        Some(Self { polygon, points, half_gap_km, circle })
+    }
+}
+
+/// A distance no greater than that between any point of one hull and any
+/// point of the other. Overlapping hulls give no bound. Otherwise the closest
+/// points lie on the outlines, each within `half_gap_km` of a sampled point.
+pub fn hull_lower_bound_km(hull1: &Hull, hull2: &Hull, model: DistanceModel) -> f64 {
+    // The half gaps are great-circle lengths, so bound geodesics through the
+    // sphere rather than mixing the two.
+    if let DistanceModel::Geodesic(ellipsoid) = model {
+        return hull_lower_bound_km(hull1, hull2, DistanceModel::GreatCircle) * ellipsoid.min_ratio();
+    }
+    if hull1.polygon.intersects(&hull2.polygon) {
+        return 0.0;
+    }
+    let nearest_samples_km = calculate_min_distance_km(&hull1.points, &hull2.points, model);
+    (nearest_samples_km - hull1.half_gap_km - hull2.half_gap_km).max(0.0)
+}
+
+/// `count` evenly spaced points along the great circle from `start` to `end`,
+/// both included, with longitudes in -180..=180.
+pub fn great_circle_waypoints(start: Point<f64>, end: Point<f64>, count: usize) -> Vec<Point<f64>> {
+    let normalize = |p: Point<f64>| Point::new((p.x() + 540.0).rem_euclid(360.0) - 180.0, p.y());
+    let (start, end) = (normalize(start), normalize(end));
+    (0..count)
+        .map(|i| {
+            let fraction = i as f64 / (count - 1) as f64;
+            normalize(start.haversine_intermediate(&end, fraction))
+        })
+        .collect()
+}
+
+pub fn calculate_min_distance_km(points1: &[Point<f64>], points2: &[Point<f64>], model: DistanceModel) -> f64 {
+    points1.iter()
+        .flat_map(|p1| points2.iter().map(move |p2| model.point_distance_m(p1, p2)))
+        .fold(f64::INFINITY, f64::min) / 1000.0
+}
+
+/// A country's outline as the distance code sees it: its vertices, and the
+/// polygons whose edges join them.
+#[derive(Debug, Clone, Copy)]
+pub struct Border<'a> {
+    pub points: &'a [Point<f64>],
+    pub geometry: &'a MultiPolygon<f64>,
+}
+
+/// Minimum distance between two countries measured between each vertex of one
+/// border and the closest point on any edge of the other, in both directions.
+/// Unlike the vertex scan this finds borders that approach each other mid-edge.
+pub fn calculate_edge_distance_km(border1: Border, border2: Border, model: DistanceModel) -> f64 {
+    let forward = min_vertex_to_edge_km(border1.points, border2.geometry, model);
+    let backward = min_vertex_to_edge_km(border2.points, border1.geometry, model);
+    forward.min(backward)
+}
+
+pub fn min_vertex_to_edge_km(points: &[Point<f64>], geometry: &MultiPolygon<f64>, model: DistanceModel) -> f64 {
+    geometry.lines_iter()
+        .flat_map(|line| points.iter().map(move |p| {
+            let nearest = nearest_on_segment(unwrap_longitudes(line, p.x()), p);
+            model.point_distance_m(p, &nearest)
+        }))
+        .fold(f64::INFINITY, f64::min) / 1000.0
+}
+
+/// The pair of border points, one from each country, that the edge-aware
+/// distance measures between.
+pub fn closest_border_points(
+    border1: Border,
+    border2: Border,
+    model: DistanceModel,
+) -> Option<(Point<f64>, Point<f64>)> {
+    let closest = |points: &[Point<f64>], geometry: &MultiPolygon<f64>| {
+        geometry.lines_iter()
+            .flat_map(|line| points.iter().map(move |p| (*p, nearest_on_segment(unwrap_longitudes(line, p.x()), p))))
+            .min_by(|a, b| model.point_distance_m(&a.0, &a.1).total_cmp(&model.point_distance_m(&b.0, &b.1)))
+    };
+    let forward = closest(border1.points, border2.geometry);
+    let backward = closest(border2.points, border1.geometry).map(|(p2, p1)| (p1, p2));
+    [forward, backward]
+        .into_iter()
+        .flatten()
+        .min_by(|a, b| model.point_distance_m(&a.0, &a.1).total_cmp(&model.point_distance_m(&b.0, &b.1)))
+}
+
+/// Closest point of a segment to `point`. Repeated vertices in the data give
+/// zero-length segments, which have no direction to project onto, so they are
+/// treated as the single point they are.
+fn nearest_on_segment(line: Line<f64>, point: &Point<f64>) -> Point<f64> {
+    if line.start == line.end {
+        return line.start_point();
+    }
+    match line.closest_point(point) {
+        Closest::Intersection(c) | Closest::SinglePoint(c) => c,
+        Closest::Indeterminate => line.start_point(),
+    }
+}
+
+/// Shifts a segment by whole turns so it starts within 180° of `longitude`,
+/// with its end following the start, keeping the planar projection valid
+/// across the antimeridian.
+fn unwrap_longitudes(line: Line<f64>, longitude: f64) -> Line<f64> {
+    let unwrap = |x: f64, reference: f64| x - ((x - reference) / 360.0).round() * 360.0;
+    let start_x = unwrap(line.start.x, longitude);
+    let end_x = unwrap(line.end.x, start_x);
+    Line::new(
+        Coord { x: start_x, y: line.start.y },
+        Coord { x: end_x, y: line.end.y },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(lon: f64, lat: f64, width: f64, height: f64) -> (Vec<Point<f64>>, MultiPolygon<f64>) {
+        let ring = vec![(lon, lat), (lon + width, lat), (lon + width, lat + height), (lon, lat + height), (lon, lat)];
+        let polygon = Polygon::new(ring.into(), Vec::new());
+        (polygon.exterior().points().collect(), MultiPolygon::new(vec![polygon]))
+    }
+
+    #[test]
+    fn edge_scan_finds_the_hand_computed_edge_to_edge_minimum() {
+        // West spans longitudes 0 to 1; East's west edge runs along longitude
+        // 2 between latitudes 0.25 and 0.75, facing the middle of West's east
+        // edge, so the nearest points are a vertex of East and the middle of
+        // an edge of West.
+        let (west_points, west) = square(0.0, 0.0, 1.0, 1.0);
+        let (east_points, east) = square(2.0, 0.25, 1.0, 0.5);
+        let west = Border { points: &west_points, geometry: &west };
+        let east = Border { points: &east_points, geometry: &east };
+
+        // From (2, 0.75) straight across to (1, 0.75): d = 2R asin(cos φ sin(Δλ/2)).
+        let radius_km = MEAN_EARTH_RADIUS_M / 1000.0;
+        let expected_km = 2.0 * radius_km * (0.75_f64.to_radians().cos() * 0.5_f64.to_radians().sin()).asin();
+        let edge_km = calculate_edge_distance_km(west, east, DistanceModel::GreatCircle);
+        assert!((edge_km - expected_km).abs() < 1e-6, "{} vs {}", edge_km, expected_km);
+
+        // The nearest vertices, (1, 1) and (2, 0.75), are farther apart.
+        let vertex_km = calculate_min_distance_km(west.points, east.points, DistanceModel::GreatCircle);
+        assert!(vertex_km > edge_km + 1.0);
+
+        let (a, b) = closest_border_points(west, east, DistanceModel::GreatCircle).unwrap();
+        assert_eq!((a.x(), a.y(), b.x(), b.y()), (1.0, 0.75, 2.0, 0.75));
+    }
+
+    #[test]
+    fn adjacent_countries_cross_on_their_shared_boundary() {
+        // East's west edge lies along West's east edge, longitude 1.
+        let (west_points, west) = square(0.0, 0.0, 1.0, 1.0);
+        let (east_points, east) = square(1.0, 0.25, 1.0, 0.5);
+        let west = Border { points: &west_points, geometry: &west };
+        let east = Border { points: &east_points, geometry: &east };
+
+        let (a, b) = closest_border_points(west, east, DistanceModel::GreatCircle).unwrap();
+        for point in [a, b] {
+            assert_eq!(point.x(), 1.0);
+            assert!((0.25..=0.75).contains(&point.y()), "{:?}", point);
+        }
+        assert!(DistanceModel::GreatCircle.point_distance_m(&a, &b) < 1e-6);
+    }
+
+    #[test]
+    fn a_custom_flattening_moves_meridian_distances_by_the_expected_amount() {
+        let km = |ellipsoid: Ellipsoid, from: (f64, f64), to: (f64, f64)| {
+            DistanceModel::Geodesic(ellipsoid).point_distance_m(&Point::from(from), &Point::from(to)) / 1000.0
+        };
+        let (equator, pole) = ((0.0, 0.0), (0.0, 90.0));
+        let wgs84_km = km(Ellipsoid::WGS84, equator, pole);
+        assert!((wgs84_km - 10_001.966).abs() < 1e-3, "{}", wgs84_km);
+
+        // A quarter meridian shrinks by about π/4 · a · Δf as the flattening grows.
+        let rounder = Ellipsoid::new(6_378_137.0, 1.0 / 300.0).unwrap();
+        let expected_km = std::f64::consts::FRAC_PI_4 * 6_378.137 * (Ellipsoid::WGS84.flattening() - rounder.flattening());
+        let difference_km = km(rounder, equator, pole) - wgs84_km;
+        assert!((difference_km - expected_km).abs() < 1e-3, "{} vs {}", difference_km, expected_km);
+
+        let sphere = Ellipsoid::new(6_378_137.0, 0.0).unwrap();
+        assert!((km(sphere, equator, pole) - std::f64::consts::FRAC_PI_2 * 6_378.137).abs() < 1e-6);
+        // The equator is the same circle whatever the flattening.
+        assert!((km(rounder, equator, (30.0, 0.0)) - km(Ellipsoid::WGS84, equator, (30.0, 0.0))).abs() < 1e-6);
+
+        assert!(Ellipsoid::new(-1.0, 0.0).is_err() && Ellipsoid::new(6_378_137.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn rhumb_lines_differ_from_great_circles_off_the_meridians() {
+        let km = |model: DistanceModel, from: (f64, f64), to: (f64, f64)| {
+            model.point_distance_m(&Point::from(from), &Point::from(to)) / 1000.0
+        };
+        // North-south, both follow the meridian.
+        let (north, south) = ((10.0, 60.0), (10.0, -20.0));
+        let along_meridian = km(DistanceModel::GreatCircle, north, south);
+        assert!((km(DistanceModel::Rhumb, north, south) - along_meridian).abs() < 1e-6);
+
+        // Diagonally at high latitude the constant bearing takes the long way.
+        let (west, east) = ((-60.0, 50.0), (60.0, 65.0));
+        let great_circle = km(DistanceModel::GreatCircle, west, east);
+        assert!(km(DistanceModel::Rhumb, west, east) > great_circle + 100.0);
+    }
+
+    #[test]
+    fn a_zero_length_segment_measures_to_its_point() {
+        let vertex = Coord { x: 1.0, y: 1.0 };
+        let point = Point::new(4.0, 5.0);
+        assert_eq!(nearest_on_segment(Line::new(vertex, vertex), &point), Point::from(vertex));
+
+        // A ring with a repeated vertex, as some data has.
+        let ring = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)];
+        let geometry = MultiPolygon::new(vec![Polygon::new(ring.into(), Vec::new())]);
+        let km = min_vertex_to_edge_km(&[point], &geometry, DistanceModel::GreatCircle);
+        assert!(km.is_finite());
+        assert_eq!(km, DistanceModel::GreatCircle.point_distance_m(&point, &Point::from(vertex)) / 1000.0);
+    }
+
+    #[test]
+    fn the_chord_between_antipodes_is_the_diameter() {
+        let diameter_m = 2.0 * MEAN_EARTH_RADIUS_M;
+        for (from, to) in [((0.0, 0.0), (180.0, 0.0)), ((30.0, 45.0), (-150.0, -45.0)), ((0.0, 90.0), (0.0, -90.0))] {
+            let chord_m = DistanceModel::Chord.point_distance_m(&Point::from(from), &Point::from(to));
+            assert!((chord_m - diameter_m).abs() < 1e-3, "{:?} to {:?}: {}", from, to, chord_m);
+        }
+        // A quarter turn apart, the chord is a side of the inscribed square.
+        let chord_m = DistanceModel::Chord.point_distance_m(&Point::new(0.0, 0.0), &Point::new(90.0, 0.0));
+        assert!((chord_m - MEAN_EARTH_RADIUS_M * 2f64.sqrt()).abs() < 1e-3);
+        assert_eq!(DistanceModel::Chord.max_distance_km(), Some(diameter_m / 1000.0));
+    }
+
+    #[test]
+    fn the_middle_waypoint_is_the_great_circle_midpoint() {
+        // Halfway along the arc is the normalised sum of the two unit vectors.
+        let midpoint = |a: Point<f64>, b: Point<f64>| {
+            let unit = |p: Point<f64>| {
+                let (lat, lon) = (p.y().to_radians(), p.x().to_radians());
+                (lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin())
+            };
+            let ((x1, y1, z1), (x2, y2, z2)) = (unit(a), unit(b));
+            let (x, y, z) = (x1 + x2, y1 + y2, z1 + z2);
+            Point::new(y.atan2(x).to_degrees(), z.atan2((x * x + y * y).sqrt()).to_degrees())
+        };
+        for (start, end) in [((2.35, 48.85), (139.69, 35.68)), ((170.0, 10.0), (-170.0, -10.0)), ((-74.0, 40.7), (-0.1, 51.5))] {
+            let (start, end) = (Point::from(start), Point::from(end));
+            let waypoints = great_circle_waypoints(start, end, 5);
+            assert_eq!(waypoints.len(), 5);
+            assert!(waypoints[0].haversine_distance(&start) < 1e-3 && waypoints[4].haversine_distance(&end) < 1e-3);
+            let (middle, expected) = (waypoints[2], midpoint(start, end));
+            assert!(middle.haversine_distance(&expected) < 1e-3, "{:?} vs {:?}", middle, expected);
+            assert!((-180.0..=180.0).contains(&middle.x()));
+        }
+    }
+}
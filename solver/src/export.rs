@@ -0,0 +1,257 @@
+//! Writing candidates out: `export` lists, KML and the interactive map.
+
+#[cfg(feature = "interactive-map")]
+use std::path::Path;
+#[cfg(feature = "interactive-map")]
+use geojson::{Feature, Value};
+use anyhow::{Result, Context};
+use crate::{Candidate, CountryData};
+use crate::session::Session;
+
+/// Handles `export <file>`: writes the candidates still in play one per line,
+/// ready for `pool from <file>` or as guesses in a `--batch` file.
+pub(crate) fn export_candidates(session: &Session, path: &str) {
+    if path.is_empty() {
+        println!("Usage: export <file>");
+        return;
+    }
+    let Some(remaining) = &session.remaining else {
+        println!("No candidates yet; guess a country first.");
+        return;
+    };
+    let contents: String = remaining.iter().map(|name| format!("{}\n", name)).collect();
+    match std::fs::write(path, contents) {
+        Ok(()) => println!("Wrote {} candidates to {}", remaining.len(), path),
+        Err(e) => println!("Error: Failed to write {}: {}", path, e),
+    }
+}
+
+/// Writes the guess and its candidates as KML placemarks with their outlines,
+/// for viewing in Google Earth.
+pub(crate) fn write_kml(
+    path: &str,
+    guess: &CountryData,
+    candidates: &[Candidate],
+    countries: &[CountryData],
+    precision: usize,
+) -> Result<()> {
+    let kml = kml_document(guess, candidates, countries, precision);
+    std::fs::write(path, kml).with_context(|| format!("Failed to write {}", path))
+}
+
+fn kml_document(guess: &CountryData, candidates: &[Candidate], countries: &[CountryData], precision: usize) -> String {
+    let mut kml = String::new();
+    kml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+    kml.push_str(&format!("<name>Mystery countries from {}</name>\n", xml_escape(&guess.name)));
+    kml.push_str("<Style id=\"guess\"><PolyStyle><color>7f0000ff</color></PolyStyle></Style>\n");
+    kml.push_str("<Style id=\"candidate\"><PolyStyle><color>7f00ff00</color></PolyStyle></Style>\n");
+
+    push_kml_placemark(&mut kml, guess, "guess", "Guessed country");
+    for candidate in candidates {
+        if let Some(country) = countries.iter().find(|c| c.name == candidate.name) {
+            let description = format!("{:.*} km from {}", precision, candidate.distance_km, guess.name);
+            push_kml_placemark(&mut kml, country, "candidate", &description);
+        }
+    }
+
+    kml.push_str("</Document>\n</kml>\n");
+    kml
+}
+
+fn push_kml_placemark(kml: &mut String, country: &CountryData, style: &str, description: &str) {
+    let ring = |ring: &geo::LineString<f64>| {
+        let coordinates: Vec<String> = ring.coords().map(|c| format!("{},{}", c.x, c.y)).collect();
+        format!("<LinearRing><coordinates>{}</coordinates></LinearRing>", coordinates.join(" "))
+    };
+
+    kml.push_str("<Placemark>\n");
+    kml.push_str(&format!("<name>{}</name>\n", xml_escape(&country.name)));
+    kml.push_str(&format!("<description>{}</description>\n", xml_escape(description)));
+    kml.push_str(&format!("<styleUrl>#{}</styleUrl>\n<MultiGeometry>\n", style));
+    for polygon in &country.geometry {
+        kml.push_str("<Polygon><outerBoundaryIs>");
+        kml.push_str(&ring(polygon.exterior()));
+        kml.push_str("</outerBoundaryIs>");
+        for interior in polygon.interiors() {
+            kml.push_str("<innerBoundaryIs>");
+            kml.push_str(&ring(interior));
+            kml.push_str("</innerBoundaryIs>");
+        }
+        kml.push_str("</Polygon>\n");
+    }
+    kml.push_str("</MultiGeometry>\n</Placemark>\n");
+}
+
+/// The guess and its candidates as GeoJSON features, tagged with a `role` of
+/// `guess` or `candidate` and the candidate's distance.
+#[cfg(feature = "interactive-map")]
+fn results_geojson(
+    guess: &CountryData,
+    candidates: &[Candidate],
+    countries: &[CountryData],
+    precision: usize,
+) -> geojson::FeatureCollection {
+    let feature = |country: &CountryData, role: &str, distance_km: Option<f64>| {
+        let mut properties = geojson::JsonObject::new();
+        properties.insert("name".to_string(), country.name.clone().into());
+        properties.insert("role".to_string(), role.into());
+        if let Some(distance_km) = distance_km {
+            properties.insert("distance_km".to_string(), format!("{:.*}", precision, distance_km).into());
+        }
+        Feature {
+            geometry: Some(geojson::Geometry::new(Value::from(&country.geometry))),
+            properties: Some(properties),
+            ..Feature::default()
+        }
+    };
+
+    let mut features = vec![feature(guess, "guess", None)];
+    for candidate in candidates {
+        if let Some(country) = countries.iter().find(|c| c.name == candidate.name) {
+            features.push(feature(country, "candidate", Some(candidate.distance_km)));
+        }
+    }
+    geojson::FeatureCollection { bbox: None, features, foreign_members: None }
+}
+
+/// Writes the results and the bundled viewer with them embedded into the temp
+/// directory, returning the viewer's path. The data is inlined because
+/// browsers won't let a `file://` page fetch a neighbouring file.
+#[cfg(feature = "interactive-map")]
+pub(crate) fn write_interactive_map(
+    guess: &CountryData,
+    candidates: &[Candidate],
+    countries: &[CountryData],
+    precision: usize,
+) -> Result<std::path::PathBuf> {
+    const VIEWER: &str = include_str!("map_viewer.html");
+
+    let directory = std::env::temp_dir().join("globle-solver");
+    std::fs::create_dir_all(&directory)
+        .with_context(|| format!("Failed to create {}", directory.display()))?;
+    let geojson = results_geojson(guess, candidates, countries, precision).to_string();
+    let geojson_path = directory.join("results.geojson");
+    std::fs::write(&geojson_path, &geojson)
+        .with_context(|| format!("Failed to write {}", geojson_path.display()))?;
+
+    // `</` inside the data would end the script element early.
+    let html = VIEWER.replace("/*RESULTS*/null", &geojson.replace("</", "<\\/"));
+    let html_path = directory.join("map.html");
+    std::fs::write(&html_path, html).with_context(|| format!("Failed to write {}", html_path.display()))?;
+    Ok(html_path)
+}
+
+#[cfg(feature = "interactive-map")]
+pub(crate) fn open_in_browser(path: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    let status = command.arg(path).status().context("no browser launcher found")?;
+    anyhow::ensure!(status.success(), "the launcher exited with {}", status);
+    Ok(())
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::update_pool;
+    use crate::test_support::{square, Solver};
+
+    /// Panics unless every element is closed in order and every `&` starts an entity.
+    fn assert_well_formed(xml: &str) {
+        let mut open = Vec::new();
+        let mut rest = xml.strip_prefix("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n").expect("an XML declaration");
+        while let Some(start) = rest.find('<') {
+            let end = start + rest[start..].find('>').expect("an unterminated tag");
+            let tag = &rest[start + 1..end];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "mismatched </{}>", name);
+            } else if !tag.ends_with('/') {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+            rest = &rest[end + 1..];
+        }
+        assert!(open.is_empty(), "unclosed {:?}", open);
+        for (i, _) in xml.match_indices('&') {
+            assert!(["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"].iter().any(|entity| xml[i..].starts_with(entity)));
+        }
+    }
+
+    #[test]
+    fn kml_is_well_formed_with_a_placemark_per_country() {
+        let solver = Solver::new(vec![
+            square("Home", 0.0, 0.0, 1.0),
+            square("Trinidad & Tobago", 3.0, 0.0, 1.0),
+            square("<Unnamed>", 6.0, 0.0, 1.0),
+        ]);
+        let candidates = solver.search("Home", "10000--10000");
+        let kml = kml_document(solver.country("Home"), &candidates, &solver.countries, 1);
+
+        assert_well_formed(&kml);
+        assert_eq!(kml.matches("<Placemark>").count(), 3);
+        assert!(kml.contains("<name>Trinidad &amp; Tobago</name>"));
+        assert!(kml.contains("<name>&lt;Unnamed&gt;</name>"));
+        assert!(kml.contains(&format!("<description>{:.1} km from Home</description>", candidates[0].distance_km)));
+    }
+
+    #[test]
+    fn the_exported_file_lists_exactly_the_remaining_candidates() {
+        let directory = std::env::temp_dir().join(format!("globle-solver-export-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("candidates.txt");
+        let path = path.to_str().unwrap();
+
+        let mut solver = Solver::fixture();
+        export_candidates(&solver.session, path);
+        assert!(std::fs::metadata(path).is_err(), "nothing to export before a guess");
+
+        solver.guess("Alpha", "1000--900");
+        export_candidates(&solver.session, path);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "Beta\nDelta Islands\n");
+        // It reads back as a pool of the same countries.
+        update_pool(&mut solver.session, &format!("from {}", path), &solver.countries);
+        assert_eq!(solver.session.pool.as_deref(), Some(solver.remaining().as_slice()));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "interactive-map")]
+    #[test]
+    fn the_map_embeds_the_guess_and_candidates_as_geojson() {
+        let solver = Solver::new(vec![square("Home", 0.0, 0.0, 1.0), square("</script>land", 3.0, 0.0, 1.0), square("Away", 6.0, 0.0, 1.0)]);
+        let candidates = solver.search("Home", "10000--10000");
+        let collection = results_geojson(solver.country("Home"), &candidates, &solver.countries, 1);
+        let roles: Vec<(String, String)> = collection.features.iter()
+            .map(|feature| {
+                let property = |key: &str| feature.property(key).and_then(|value| value.as_str()).unwrap_or_default().to_string();
+                (property("name"), property("role"))
+            })
+            .collect();
+        let expected = [("Home", "guess"), ("</script>land", "candidate"), ("Away", "candidate")];
+        assert_eq!(roles, expected.map(|(name, role)| (name.to_string(), role.to_string())));
+        assert!(collection.features[0].property("distance_km").is_none());
+        assert_eq!(collection.features[2].property("distance_km").and_then(|value| value.as_str()), Some(format!("{:.1}", candidates[1].distance_km).as_str()));
+
+        let html_path = write_interactive_map(solver.country("Home"), &candidates, &solver.countries, 1).unwrap();
+        let html = std::fs::read_to_string(&html_path).unwrap();
+        let geojson = std::fs::read_to_string(html_path.with_file_name("results.geojson")).unwrap();
+        assert!(!html.contains("/*RESULTS*/null"));
+        assert!(html.contains(&geojson.replace("</", "<\\/")));
+        // The name can't close the viewer's script element.
+        assert_eq!(html.matches("</script>").count(), include_str!("map_viewer.html").matches("</script>").count());
+    }
+}
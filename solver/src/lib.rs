@@ -0,0 +1,4 @@
+//! The distance code behind the solver, as a library so the benchmarks in
+//! `benches/` can measure it.
+
+pub mod distance;
@@ -1,250 +1,1998 @@
 use std::fs::File;
-use std::io::{self, BufReader, Write};
-use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use serde_json::from_reader;
-use geo::{prelude::*, Point};
-use geojson::{Feature, GeoJson, Value};
+use geo::{prelude::*, MultiPolygon, Point};
+use geojson::Feature;
 use anyhow::{Result, Context};
 
+mod cache;
+mod data;
+mod export;
+mod commands;
+mod output;
+mod query;
+mod server;
+mod session;
+#[cfg(test)]
+mod test_support;
+
+use cache::{CachePrecision, DistanceCache, load_cache, save_cache};
+use data::{KM_PER_DEGREE, UnknownGeometryPolicy, apply_name_translations, apply_populations, check_data, estimate_data_uncertainty_km, generate_dataset, load_country_data, load_name_translations, load_populations, simplify_countries, validate_data};
+use untitled3::distance::{Border, DistanceModel, Ellipsoid, Hull, calculate_edge_distance_km, calculate_min_distance_km, hull_lower_bound_km};
+use commands::Commands;
+use output::{SortOrder, Tiebreak, candidate_groups, in_columns, order_candidates, terminal_width};
+use export::write_kml;
+#[cfg(feature = "interactive-map")]
+use export::{open_in_browser, write_interactive_map};
+use query::{DistanceInput, format_km, near_max_warning, parse_query};
+use server::run_server;
+use session::Session;
+
 const MAX_MARGIN_INCREASE: f64 = 100.0;
 const MARGIN_STEP: f64 = 1.0;
 
-#[derive(Debug)]
-struct DistanceCache {
-    cache: HashMap<(String, String), f64>,
+/// Options given on the command line.
+#[derive(Debug, Clone)]
+struct Config {
+    cache_precision: CachePrecision,
+    /// Treat a distance entered without a margin as the game's rounding interval.
+    game_uncertainty: bool,
+    /// Granularity the game rounds displayed distances to.
+    rounding_km: f64,
+    /// Testing aid: shuffle the loaded countries with this seed to flush out
+    /// results that depend on dataset order.
+    shuffle_seed: Option<u64>,
+    /// Picks the hidden country in `--simulate`, for replaying a round.
+    seed: Option<u64>,
+    /// Decimal places used when printing distances.
+    precision: usize,
+    /// Print candidate names without their distances.
+    names_only: bool,
+    /// Use the original vertex-to-vertex scan instead of the edge-aware distance.
+    vertex_only: bool,
+    /// JSON file mapping dataset names to preferred display names.
+    names_file: Option<String>,
+    /// Follow the candidate list with a single most likely answer.
+    recommend: bool,
+    model: DistanceModel,
+    /// Countries already guessed before starting, excluded from candidates.
+    guessed: Vec<String>,
+    /// Show each distance as a share of the farthest candidate's.
+    relative: bool,
+    /// Order of candidates that tie under `sort`.
+    tiebreak: Tiebreak,
+    /// Only landlocked (`true`) or only coastal (`false`) candidates.
+    landlocked: Option<bool>,
+    /// JSON object of populations by country name, shown with each candidate.
+    population_file: Option<String>,
+    /// Only candidates with at least this many people.
+    min_population: Option<f64>,
+    /// Group candidates under alphabetical headers.
+    group_alpha: bool,
+    /// List candidates under the compass sector they lie in from the guess.
+    group_bearing: bool,
+    /// Show the compass direction from the guess to each candidate.
+    bearing: bool,
+    /// Show the exact bearing in degrees as well as the compass direction.
+    bearing_degrees: bool,
+    /// Lay candidates out in columns across the terminal.
+    columns: bool,
+    /// Show how many polygons make up each candidate.
+    polygons: bool,
+    /// Search on a worker thread with a percentage shown; Ctrl-C cancels.
+    progress: bool,
+    /// Play against a hidden country instead of solving.
+    simulate: bool,
+    /// In simulation, answer with hot/warm/cold instead of a distance.
+    hot_cold: Option<HotColdBands>,
+    /// Separates the distance from its margin in typed input.
+    delimiter: String,
+    sort: SortOrder,
+    /// GeoJSON file the countries are read from.
+    data_path: String,
+    /// Widen every search band by the uncertainty estimated from the data.
+    widen_by_data_uncertainty: bool,
+    /// That estimate, once the data is loaded.
+    data_uncertainty_km: Option<f64>,
+    /// Warn when a query's band reaches within this many km of the maximum
+    /// distance; 0 turns the warning off.
+    near_max_km: f64,
+    /// Pairs with more border vertices than this between them are measured
+    /// between centroids instead, so one huge polygon can't stall a search.
+    max_pair_vertices: Option<usize>,
+    /// Stop widening a margin before it matches more than this many
+    /// countries; 0 for no limit.
+    max_expanded_candidates: usize,
+    /// Douglas-Peucker tolerance in degrees applied to every border at load.
+    simplify: Option<f64>,
+    /// How many near misses to list when a query matches nothing; 0 for just
+    /// the nearest one.
+    nearest_on_empty: usize,
+    /// What to do when no distance is entered after a guess.
+    on_empty_distance: EmptyDistancePolicy,
+    /// What to do with features that aren't polygons.
+    on_unknown_geometry: UnknownGeometryPolicy,
+    /// File of queries to answer in place of typed input.
+    batch: Option<String>,
+    /// Address to serve the streaming HTTP endpoint on instead of prompting.
+    serve: Option<String>,
+    /// Where distances are loaded from at start and saved to on exit.
+    cache_file: Option<String>,
+    /// Only load and validate the data, then exit.
+    check_data: bool,
+    /// Like `check_data` but lists every problem found.
+    validate: bool,
+    /// Write this many random countries to a GeoJSON file for benchmarking,
+    /// then exit.
+    generate_dataset: Option<(usize, String)>,
+    /// Write each query's guess and candidates to this KML file.
+    kml_out: Option<String>,
+    /// After each query, show which candidates the border, centroid and
+    /// geodesic models each find.
+    compare_models: bool,
+    /// Ellipsoid for geodesic distances.
+    ellipsoid: Ellipsoid,
+    /// Open each query's guess and candidates on a map in the browser.
+    interactive_map: bool,
+    /// Filter enclave pairs by their computed distance instead of always
+    /// including them.
+    no_special: bool,
 }
 
-impl DistanceCache {
-    fn new() -> Self {
+impl Default for Config {
+    fn default() -> Self {
         Self {
-            cache: HashMap::new(),
+            cache_precision: CachePrecision::Full,
+            game_uncertainty: false,
+            rounding_km: 1.0,
+            shuffle_seed: None,
+            seed: None,
+            precision: 1,
+            names_only: false,
+            vertex_only: false,
+            names_file: None,
+            recommend: false,
+            model: DistanceModel::GreatCircle,
+            guessed: Vec::new(),
+            relative: false,
+            tiebreak: Tiebreak::Alpha,
+            landlocked: None,
+            population_file: None,
+            min_population: None,
+            group_alpha: false,
+            group_bearing: false,
+            bearing: false,
+            bearing_degrees: false,
+            polygons: false,
+            columns: false,
+            progress: false,
+            simulate: false,
+            hot_cold: None,
+            delimiter: "--".to_string(),
+            sort: SortOrder::Name,
+            data_path: "country_data.json".to_string(),
+            widen_by_data_uncertainty: false,
+            data_uncertainty_km: None,
+            simplify: None,
+            max_pair_vertices: None,
+            max_expanded_candidates: 50,
+            near_max_km: 500.0,
+            nearest_on_empty: 0,
+            on_empty_distance: EmptyDistancePolicy::Reprompt,
+            on_unknown_geometry: UnknownGeometryPolicy::Skip,
+            batch: None,
+            serve: None,
+            cache_file: None,
+            check_data: false,
+            validate: false,
+            generate_dataset: None,
+            kml_out: None,
+            interactive_map: false,
+            compare_models: false,
+            ellipsoid: Ellipsoid::WGS84,
+            no_special: false,
         }
     }
+}
 
-    fn get_or_calculate<F>(&mut self, country1: &str, country2: &str, calc_fn: F) -> Option<f64>
-    where
-        F: FnOnce() -> Option<f64>,
-    {
-        let key = if country1 < country2 {
-            (country1.to_string(), country2.to_string())
-        } else {
-            (country2.to_string(), country1.to_string())
-        };
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config> {
+    let mut config = Config::default();
 
-        if let Some(&distance) = self.cache.get(&key) {
-            Some(distance)
-        } else {
-            let distance = calc_fn()?;
-            self.cache.insert(key, distance);
-            Some(distance)
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cache-precision" => {
+                config.cache_precision = option_value(&mut args, &arg)?.parse()?;
+            }
+            "--game-uncertainty" => config.game_uncertainty = true,
+            "--rounding" => {
+                config.rounding_km = option_value(&mut args, &arg)?
+                    .parse()
+                    .context("Invalid rounding granularity")?;
+                if config.rounding_km <= 0.0 {
+                    anyhow::bail!("Rounding granularity must be positive");
+                }
+            }
+            "--shuffle-data" => {
+                config.shuffle_seed = Some(option_value(&mut args, &arg)?.parse().context("Invalid shuffle seed")?);
+            }
+            "--precision" => {
+                config.precision = option_value(&mut args, &arg)?.parse().context("Invalid precision")?;
+            }
+            "--names-only" => config.names_only = true,
+            "--relative" => config.relative = true,
+            "--tiebreak" => config.tiebreak = option_value(&mut args, &arg)?.parse()?,
+            "--landlocked" => config.landlocked = Some(true),
+            "--coastal" => config.landlocked = Some(false),
+            "--population" => config.population_file = Some(option_value(&mut args, &arg)?),
+            "--min-population" => {
+                config.min_population = Some(option_value(&mut args, &arg)?.parse().context("Invalid minimum population")?);
+            }
+            "--group-alpha" => config.group_alpha = true,
+            "--group-bearing" => config.group_bearing = true,
+            "--bearing" => config.bearing = true,
+            "--bearing-degrees" => config.bearing_degrees = true,
+            "--polygons" => config.polygons = true,
+            "--columns" => config.columns = true,
+            "--progress" => config.progress = true,
+            "--data" => config.data_path = option_value(&mut args, &arg)?,
+            "--data-uncertainty" => config.widen_by_data_uncertainty = true,
+            "--near-max-warning" => {
+                config.near_max_km = option_value(&mut args, &arg)?
+                    .parse()
+                    .context("Invalid near-maximum distance")?;
+            }
+            "--max-expanded-candidates" => {
+                config.max_expanded_candidates = option_value(&mut args, &arg)?.parse().context("Invalid candidate limit")?;
+            }
+            "--max-pair-vertices" => {
+                config.max_pair_vertices = Some(option_value(&mut args, &arg)?.parse().context("Invalid vertex limit")?);
+            }
+            "--simplify" => {
+                let tolerance: f64 = option_value(&mut args, &arg)?
+                    .parse()
+                    .context("Invalid simplification tolerance")?;
+                if tolerance <= 0.0 {
+                    anyhow::bail!("Simplification tolerance must be positive");
+                }
+                config.simplify = Some(tolerance);
+            }
+            "--show-nearest-on-empty" => {
+                config.nearest_on_empty = option_value(&mut args, &arg)?.parse().context("Invalid number of near misses")?;
+            }
+            "--on-empty-distance" => config.on_empty_distance = option_value(&mut args, &arg)?.parse()?,
+            "--on-unknown-geometry" => config.on_unknown_geometry = option_value(&mut args, &arg)?.parse()?,
+            "--batch" => config.batch = Some(option_value(&mut args, &arg)?),
+            "--serve" => config.serve = Some(option_value(&mut args, &arg)?),
+            "--cache-file" => config.cache_file = Some(option_value(&mut args, &arg)?),
+            "--check-data" => config.check_data = true,
+            "--validate" => config.validate = true,
+            "--kml-out" => config.kml_out = Some(option_value(&mut args, &arg)?),
+            "--interactive-map" => {
+                if !cfg!(feature = "interactive-map") {
+                    anyhow::bail!("--interactive-map needs a build with `--features interactive-map`");
+                }
+                config.interactive_map = true;
+            }
+            "--sort" => config.sort = option_value(&mut args, &arg)?.parse()?,
+            "--simulate" => config.simulate = true,
+            "--seed" => config.seed = Some(option_value(&mut args, &arg)?.parse().context("Invalid seed")?),
+            "--delimiter" => {
+                let delimiter = option_value(&mut args, &arg)?;
+                // The delimiter must not be confused with the numbers or the `+` of
+                // an asymmetric margin.
+                if delimiter.is_empty() || delimiter.chars().any(|c| c.is_ascii_digit() || c == '.' || c == '+') {
+                    anyhow::bail!("Delimiter '{}' can't be empty or contain digits, '.' or '+'", delimiter);
+                }
+                config.delimiter = delimiter;
+            }
+            "--hot-cold" => {
+                config.hot_cold = Some(HotColdBands { hot_km: 500.0, warm_km: 2000.0 });
+            }
+            "--hot-cold-bands" => config.hot_cold = Some(option_value(&mut args, &arg)?.parse()?),
+            "--vertex-only" => config.vertex_only = true,
+            "--no-special" => config.no_special = true,
+            "--recommend" => config.recommend = true,
+            "--guessed" => {
+                let names = option_value(&mut args, &arg)?;
+                config.guessed.extend(names.split(',').map(str::trim).filter(|n| !n.is_empty()).map(String::from));
+            }
+            "--compare-models" => config.compare_models = true,
+            "--semi-major" => {
+                let semi_major_m = option_value(&mut args, &arg)?.parse().context("Invalid semi-major axis")?;
+                config.ellipsoid = Ellipsoid::new(semi_major_m, config.ellipsoid.flattening())?;
+            }
+            "--flattening" => {
+                let flattening = parse_flattening(&option_value(&mut args, &arg)?)?;
+                config.ellipsoid = Ellipsoid::new(config.ellipsoid.semi_major_m(), flattening)?;
+            }
+            "--model" => config.model = option_value(&mut args, &arg)?.parse()?,
+            "--blend" => {
+                let weight = option_value(&mut args, &arg)?;
+                let weight: f64 = weight.parse().with_context(|| format!("Invalid blend weight '{}'", weight))?;
+                config.model = DistanceModel::blend(weight)?;
+            }
+            "--names" => config.names_file = Some(option_value(&mut args, &arg)?),
+            "--generate-dataset" => {
+                let count = option_value(&mut args, &arg)?.parse().context("Invalid number of countries")?;
+                if count == 0 {
+                    anyhow::bail!("--generate-dataset needs at least one country; an empty dataset can't be loaded");
+                }
+                config.generate_dataset = Some((count, option_value(&mut args, &arg)?));
+            }
+            _ => anyhow::bail!("Unknown option '{}'", arg),
         }
     }
+
+    if config.group_alpha && config.group_bearing {
+        anyhow::bail!("--group-alpha and --group-bearing can't be combined");
+    }
+    if let DistanceModel::Geodesic(_) = config.model {
+        config.model = DistanceModel::Geodesic(config.ellipsoid);
+    }
+
+    let needs_input = config.serve.is_none()
+        && !config.check_data
+        && !config.validate
+        && config.generate_dataset.is_none();
+    if config.data_path == "-" && config.batch.is_none() && needs_input {
+        anyhow::bail!("--data - reads the countries from stdin, so queries need --batch <file>");
+    }
+
+    Ok(config)
+}
+
+/// Flattening as a decimal (`0.00335`) or as one over the inverse flattening
+/// (`1/298.257223563`), the way it is usually quoted.
+fn parse_flattening(text: &str) -> Result<f64> {
+    let invalid = || format!("Invalid flattening '{}'", text);
+    match text.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator: f64 = numerator.trim().parse().with_context(invalid)?;
+            let denominator: f64 = denominator.trim().parse().with_context(invalid)?;
+            Ok(numerator / denominator)
+        }
+        None => text.trim().parse().with_context(invalid),
+    }
+}
+
+/// The mystery country a simulation with `seed` plays for.
+fn hidden_country(countries: &[CountryData], seed: u64) -> &CountryData {
+    &countries[SplitMix64::new(seed).below(countries.len())]
+}
+
+/// Small deterministic PRNG so seeded runs are reproducible without extra dependencies.
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0.0..1.0`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `low..high`.
+    fn between(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.unit()
+    }
+
+    /// Uniform index in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+fn option_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String> {
+    args.next().with_context(|| format!("Missing value for {}", flag))
 }
 
 #[derive(Clone)]
 struct CountryData {
+    /// Name shown to and typed by the user (after any `--names` translation).
     name: String,
+    /// Name as it appears in the dataset.
+    source_name: String,
     points: Vec<Point<f64>>,
+    geometry: MultiPolygon<f64>,
+    /// Convex hull for cheap lower bounds; `None` where a planar hull is meaningless.
+    hull: Option<Hull>,
+    metadata: CountryMetadata,
+}
+
+impl CountryData {
+    fn border(&self) -> Border<'_> {
+        Border { points: &self.points, geometry: &self.geometry }
+    }
+}
+
+/// Descriptive fields read from the feature properties where present.
+#[derive(Debug, Clone, Default)]
+struct CountryMetadata {
+    continent: Option<String>,
+    region: Option<String>,
+    subregion: Option<String>,
+    /// Estimated population (`POP_EST`).
+    population: Option<f64>,
+    /// `LANDLOCKED`, given as a boolean, 0/1 or yes/no.
+    landlocked: Option<bool>,
+}
+
+impl CountryMetadata {
+    fn from_feature(feature: &Feature) -> Self {
+        let field = |key: &str| {
+            feature.properties.as_ref()?
+                .get(key)?
+                .as_str()
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        Self {
+            continent: field("CONTINENT"),
+            region: field("REGION_UN"),
+            subregion: field("SUBREGION"),
+            population: feature.property("POP_EST").and_then(serde_json::Value::as_f64),
+            landlocked: feature.property("LANDLOCKED").and_then(|value| match value {
+                serde_json::Value::Bool(landlocked) => Some(*landlocked),
+                serde_json::Value::Number(n) => n.as_f64().map(|n| n != 0.0),
+                serde_json::Value::String(s) => match s.to_ascii_lowercase().as_str() {
+                    "yes" | "true" | "1" => Some(true),
+                    "no" | "false" | "0" => Some(false),
+                    _ => None,
+                },
+                _ => None,
+            }),
+        }
+    }
+
+    /// Whether the country belongs to `name` as a continent, UN region or subregion.
+    fn in_region(&self, name: &str) -> bool {
+        [&self.continent, &self.region, &self.subregion]
+            .into_iter()
+            .flatten()
+            .any(|region| region.eq_ignore_ascii_case(name))
+    }
 }
 
 fn main() -> Result<()> {
+    let mut config = parse_args(std::env::args().skip(1))?;
+
+    if config.check_data {
+        check_data(&config.data_path, config.on_unknown_geometry);
+    }
+    if config.validate {
+        validate_data(&config.data_path, config.on_unknown_geometry);
+    }
+    if let Some((count, path)) = &config.generate_dataset {
+        return generate_dataset(*count, path, config.seed.unwrap_or_default());
+    }
+
     println!("Country Distance Calculator");
     println!("==========================");
+    println!("{}", metric_header(&config));
 
-    let file = File::open("country_data.json").context("Failed to open country_data.json")?;
-    let reader = BufReader::new(file);
-    let geojson: GeoJson = from_reader(reader).context("Invalid GeoJSON format")?;
+    let mut country_geometries = load_country_data(&config.data_path, config.on_unknown_geometry)?;
+    if let Some(path) = &config.names_file {
+        let names = load_name_translations(path)?;
+        apply_name_translations(&mut country_geometries, &names);
+    }
+    if let Some(path) = &config.population_file {
+        let populations = load_populations(path)?;
+        apply_populations(&mut country_geometries, &populations);
+    }
+    if config.min_population.is_some() && country_geometries.iter().all(|c| c.metadata.population.is_none()) {
+        println!("Warning: no country has a population, so --min-population filters nothing");
+    }
+    if let Some(seed) = config.shuffle_seed {
+        SplitMix64::new(seed).shuffle(&mut country_geometries);
+    }
+    if let Some(tolerance) = config.simplify {
+        let (before, after) = simplify_countries(&mut country_geometries, tolerance);
+        println!(
+            "Simplified borders from {} to {} vertices; distances may shift by up to {:.*} km",
+            before, after, config.precision, 2.0 * tolerance * KM_PER_DEGREE
+        );
+    }
+    if config.widen_by_data_uncertainty {
+        let uncertainty_km = estimate_data_uncertainty_km(&country_geometries);
+        println!("Data uncertainty: every search band is widened by {:.*} km", config.precision, uncertainty_km);
+        config.data_uncertainty_km = Some(uncertainty_km);
+    }
+    let config = config;
 
-    let countries = match geojson {
-        GeoJson::FeatureCollection(fc) => fc.features,
-        _ => anyhow::bail!("GeoJSON is not a FeatureCollection"),
+    let mut input: Box<dyn BufRead> = match &config.batch {
+        Some(path) => {
+            let file = File::open(path).with_context(|| format!("Failed to open batch file {}", path))?;
+            Box::new(BufReader::new(file))
+        }
+        None => Box::new(io::stdin().lock()),
     };
+    let echo = config.batch.is_some();
 
-    // Pre-process all country geometries
-    let country_geometries: Vec<CountryData> = countries.iter()
-        .filter_map(|country| {
-            let name = country.properties.as_ref()?
-                .get("NAME")?
-                .as_str()?
-                .to_string();
-            let points = extract_points(country)?;
-            Some(CountryData { name, points })
+    let cache = Arc::new(Mutex::new(DistanceCache::new(config.cache_precision)));
+    load_cache(&cache, &config, &country_geometries);
+    if let Some(address) = &config.serve {
+        return run_server(address, &country_geometries, &cache, &config);
+    }
+    if config.simulate {
+        let result = run_simulation(&country_geometries, &cache, &config, &mut *input, echo);
+        save_cache(&cache, &config, &country_geometries);
+        return result;
+    }
+
+    let progress = Arc::new(SearchProgress::default());
+    if config.progress {
+        // Ctrl-C stops a running search; at the prompt it still exits.
+        let progress = Arc::clone(&progress);
+        ctrlc::set_handler(move || {
+            if !progress.cancel() {
+                std::process::exit(130);
+            }
         })
-        .collect();
+        .context("Failed to install the Ctrl-C handler")?;
+    }
 
-    let cache = Arc::new(Mutex::new(DistanceCache::new()));
+    let mut summary = BatchSummary::default();
+    let mut commands = Commands::new(&country_geometries, &cache, &config);
+    let mut session = Session {
+        landlocked: config.landlocked,
+        min_population: config.min_population,
+        ..Session::default()
+    };
+    if config.landlocked.is_some() && country_geometries.iter().all(|c| c.metadata.landlocked.is_none()) {
+        println!("Warning: the data has no LANDLOCKED property, so --landlocked/--coastal filter nothing");
+    }
+    for name in &config.guessed {
+        match find_country(&country_geometries, name) {
+            Some(country) => session.mark_guessed(&country.name),
+            None => println!("Warning: Guessed country '{}' not found in database", name),
+        }
+    }
 
     loop {
-        print!("\nEnter the country you guessed (or 'quit' to exit): ");
+        // Whatever the last command measured, the fallbacks are noted once.
+        if let Some(note) = fallback_note(&cache, &config) {
+            println!("\n{}", note);
+        }
+        print!("\nEnter the country you guessed ('help' for commands, 'quit' to exit): ");
         io::stdout().flush()?;
         let mut guessed_country_name = String::new();
-        io::stdin().read_line(&mut guessed_country_name)?;
+        let read = read_input(&mut *input, &mut guessed_country_name, echo)?;
         let guessed_country_name = guessed_country_name.trim();
 
-        if guessed_country_name.eq_ignore_ascii_case("quit") {
+        if read == 0 || guessed_country_name.eq_ignore_ascii_case("quit") {
             println!("Thank you for using the Country Distance Calculator!");
             break;
         }
 
-        let guessed_country = match country_geometries.iter().find(|c| {
-            c.name.eq_ignore_ascii_case(guessed_country_name)
-        }) {
+        if commands.run(guessed_country_name, &mut session, &mut *input, echo)? {
+            continue;
+        }
+
+        let guessed_country = match find_country(&country_geometries, guessed_country_name) {
             Some(country) => country,
             None => {
-                println!("Error: Country '{}' not found in database", guessed_country_name);
-                continue;
+                let suggestions = suggest_countries(&country_geometries, guessed_country_name, MAX_SUGGESTIONS);
+                if suggestions.is_empty() {
+                    println!("Error: Country '{}' not found in database", guessed_country_name);
+                    continue;
+                }
+
+                println!("Country '{}' not found. Did you mean:", guessed_country_name);
+                for (i, suggestion) in suggestions.iter().enumerate() {
+                    println!("  {}) {}", i + 1, suggestion.name);
+                }
+                print!("Pick a number or type another country: ");
+                io::stdout().flush()?;
+                let mut selection = String::new();
+                read_input(&mut *input, &mut selection, echo)?;
+
+                match pick_suggestion(&selection, &suggestions, &country_geometries) {
+                    Ok(country) => country,
+                    Err(message) => {
+                        println!("{}", message);
+                        continue;
+                    }
+                }
             }
         };
 
-        print!("Enter the distance (km) and optional margin (e.g., 500--50): ");
-        io::stdout().flush()?;
-        let mut distance_input = String::new();
-        io::stdin().read_line(&mut distance_input)?;
+        let distance_input = read_distance(&mut *input, echo, &guessed_country.name, &config)?;
+        let Some(distance_input) = distance_input else {
+            continue;
+        };
+
+        session.mark_guessed(&guessed_country.name);
 
-        let (known_distance_km, initial_margin) = match parse_distance_input(&distance_input) {
+        let initial_query = match parse_query(&distance_input, &config) {
             Ok(result) => result,
             Err(e) => {
                 println!("Error parsing distance: {}", e);
                 continue;
             }
         };
+        if let Some(warning) = near_max_warning(&initial_query, &config) {
+            println!("{}", warning);
+        }
 
-        let mut margin_error_km = initial_margin;
-        let mut possible_countries;
-
-        loop {
-            possible_countries = find_mystery_countries(
+        let started = std::time::Instant::now();
+        let result = session.result_or_search(&guessed_country.name, &initial_query, |session| {
+            let search = || search_with_expansion(
                 guessed_country,
-                known_distance_km,
-                margin_error_km,
+                initial_query,
                 &country_geometries,
-                Arc::clone(&cache)
+                &cache,
+                &config,
+                session,
+                &progress,
             );
-
-            if !possible_countries.is_empty() || margin_error_km >= MAX_MARGIN_INCREASE {
-                break;
-            }
-
-            margin_error_km += MARGIN_STEP;
-            println!("No countries found, increasing search margin to {} km...", margin_error_km);
-        }
+            let result = if config.progress {
+                search_in_background(search, &progress)
+            } else {
+                search()
+            };
+            (!progress.is_cancelled()).then_some(result)
+        });
+        let Some((query, mut possible_countries)) = result else {
+            println!("Search cancelled.");
+            continue;
+        };
+        summary.record(possible_countries.len(), started.elapsed());
 
         if possible_countries.is_empty() {
-            println!("\nNo countries found even with increased margin of {} km.", margin_error_km);
+            println!("\nNo countries found even with increased margin of {}.", query.describe_margin(config.precision));
+            if config.nearest_on_empty > 0 {
+                let misses = nearest_misses(
+                    guessed_country,
+                    &query,
+                    &country_geometries,
+                    &cache,
+                    &config,
+                    &session,
+                    config.nearest_on_empty,
+                );
+                println!("Nearest misses:");
+                for miss in misses {
+                    println!(
+                        "- {} — {:.*} km ({:+.*} km)",
+                        miss.name,
+                        config.precision,
+                        miss.distance_km,
+                        config.precision,
+                        miss.distance_km - query.distance_km,
+                    );
+                }
+            }
         } else {
-            if margin_error_km > initial_margin {
-                println!("\nFound countries with adjusted margin of {} km:", margin_error_km);
+            if query != initial_query {
+                println!("\nFound countries with adjusted margin of {}:", query.describe_margin(config.precision));
             }
             println!("\nPossible mystery countries ({} found):", possible_countries.len());
-            for country_name in possible_countries {
-                println!("- {}", country_name);
+            let farthest_km = possible_countries.iter().map(|c| c.distance_km).fold(0.0, f64::max);
+            let tied = order_candidates(
+                &mut possible_countries,
+                config.sort,
+                config.tiebreak,
+                &query,
+                config.precision,
+                &country_geometries,
+            );
+            let groups = candidate_groups(&possible_countries, tied, guessed_country, &country_geometries, &config, farthest_km);
+            let width = if config.columns { terminal_width() } else { None };
+            for (group, group_lines) in groups {
+                if let Some(header) = group {
+                    println!("\n{}", header);
+                }
+                for row in in_columns(&group_lines, width) {
+                    println!("{}", row);
+                }
+            }
+            if config.recommend {
+                // The session isn't updated until below, so this is the
+                // remaining list from before this guess.
+                if let Some(best) = recommend_candidate(&possible_countries, &query, session.remaining.as_deref()) {
+                    println!(
+                        "\nMost likely answer: {} (its {:.*} km is closest to the band's centre, {} km, off by {:.*} km)",
+                        best.name,
+                        config.precision,
+                        best.distance_km,
+                        format_km(query.centre_km(), config.precision),
+                        config.precision,
+                        (best.distance_km - query.centre_km()).abs(),
+                    );
+                }
+            }
+
+            if let Some(path) = &config.kml_out {
+                match write_kml(path, guessed_country, &possible_countries, &country_geometries, config.precision) {
+                    Ok(()) => println!("\nWrote KML to {}", path),
+                    Err(e) => println!("\nError writing KML: {:#}", e),
+                }
+            }
+            if config.compare_models {
+                println!("\n{}", model_comparison(guessed_country, &query, &country_geometries, &cache, &config, &session));
+            }
+            #[cfg(feature = "interactive-map")]
+            if config.interactive_map {
+                match write_interactive_map(guessed_country, &possible_countries, &country_geometries, config.precision) {
+                    Ok(path) => {
+                        if let Err(e) = open_in_browser(&path) {
+                            println!("\nCouldn't open a browser ({:#}); the map is at {}", e, path.display());
+                        }
+                    }
+                    Err(e) => println!("\nError writing the map: {:#}", e),
+                }
+            }
+
+            session.record(&guessed_country.name, query, &possible_countries);
+            if session.constraints.len() > 1 {
+                let remaining = session.remaining.as_deref().unwrap_or_default();
+                let guesses: Vec<String> = session.constraints.iter().map(|c| c.describe(config.precision)).collect();
+                println!("\nRemaining after {} ({}): {}", guesses.join("; "), remaining.len(), remaining.join(", "));
             }
         }
     }
 
+    if let Some(note) = fallback_note(&cache, &config) {
+        println!("\n{}", note);
+    }
+    if config.batch.is_some() {
+        println!("\n{}", summary.describe());
+    }
+    save_cache(&cache, &config, &country_geometries);
     Ok(())
 }
 
-fn parse_distance_input(input: &str) -> Result<(f64, f64)> {
-    let input = input.trim();
-    let parts: Vec<&str> = input.split("--").collect();
+/// The startup line naming what distance the answers are in.
+fn metric_header(config: &Config) -> String {
+    format!("Using {}{}", config.model.describe(config.vertex_only), config.model.game_note())
+}
 
-    match parts.len() {
-        1 => {
-            let distance = parts[0].parse().context("Invalid distance format")?;
-            if distance < 0.0 {
-                anyhow::bail!("Distance cannot be negative");
-            }
-            Ok((distance, 0.0))
+/// Totals over the queries of a `--batch` run.
+#[derive(Debug, Default)]
+struct BatchSummary {
+    queries: usize,
+    with_candidates: usize,
+    candidates: usize,
+    search_time: std::time::Duration,
+}
+
+impl BatchSummary {
+    fn record(&mut self, candidates: usize, search_time: std::time::Duration) {
+        self.queries += 1;
+        if candidates > 0 {
+            self.with_candidates += 1;
+        }
+        self.candidates += candidates;
+        self.search_time += search_time;
+    }
+
+    fn describe(&self) -> String {
+        let average = if self.queries == 0 { 0.0 } else { self.candidates as f64 / self.queries as f64 };
+        format!(
+            "Batch summary\n-------------\nQueries: {}\nWith candidates: {}\nAverage candidates per query: {:.1}\nSearch time: {:.2} s",
+            self.queries,
+            self.with_candidates,
+            average,
+            self.search_time.as_secs_f64(),
+        )
+    }
+}
+
+/// Distance bands used to answer simulated guesses with a word instead of a number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HotColdBands {
+    /// Guesses closer than this are "hot".
+    hot_km: f64,
+    /// Guesses closer than this (but not hot) are "warm"; the rest are "cold".
+    warm_km: f64,
+}
+
+impl HotColdBands {
+    fn label(&self, distance_km: f64) -> &'static str {
+        if distance_km < self.hot_km {
+            "hot"
+        } else if distance_km < self.warm_km {
+            "warm"
+        } else {
+            "cold"
         }
-        2 => {
-            let distance = parts[0].parse().context("Invalid distance format")?;
-            let margin = parts[1].parse().context("Invalid margin format")?;
-            if distance < 0.0 || margin < 0.0 {
-                anyhow::bail!("Distance and margin must be non-negative");
+    }
+}
+
+impl std::str::FromStr for HotColdBands {
+    type Err = anyhow::Error;
+
+    /// Parses `hot,warm` limits in km, e.g. `500,2000`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (hot, warm) = s.split_once(',').context("Use hot,warm limits in km, e.g. 500,2000")?;
+        let hot_km: f64 = hot.trim().parse().context("Invalid hot limit")?;
+        let warm_km: f64 = warm.trim().parse().context("Invalid warm limit")?;
+        if hot_km <= 0.0 || warm_km <= hot_km {
+            anyhow::bail!("Hot/cold limits must be positive with warm above hot");
+        }
+        Ok(Self { hot_km, warm_km })
+    }
+}
+
+/// Practice mode: the solver picks a hidden country and answers guesses the
+/// way the game does, until the player finds it.
+fn run_simulation(
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+    input: &mut dyn BufRead,
+    echo: bool,
+) -> Result<()> {
+    if countries.is_empty() {
+        anyhow::bail!("No countries loaded to simulate with");
+    }
+    let seed = config.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    let hidden = hidden_country(countries, seed);
+
+    println!("\nSimulation: a mystery country has been chosen. Type 'give up' to reveal it.");
+    if config.seed.is_none() {
+        println!("(Replay this round with --seed {}.)", seed);
+    }
+    let mut guesses = 0;
+    loop {
+        print!("\nYour guess: ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if read_input(input, &mut line, echo)? == 0 {
+            return Ok(());
+        }
+        let input = line.trim();
+
+        if input.eq_ignore_ascii_case("give up") || input.eq_ignore_ascii_case("quit") {
+            println!("The mystery country was {}.", hidden.name);
+            return Ok(());
+        }
+
+        let Some(guess) = find_country(countries, input) else {
+            println!("Error: Country '{}' not found in database", input);
+            continue;
+        };
+        guesses += 1;
+
+        if guess.name == hidden.name {
+            println!("Correct! The mystery country is {} ({} guesses).", hidden.name, guesses);
+            return Ok(());
+        }
+
+        let distance_km = cached_distance_km(cache, guess, hidden, config).unwrap_or(f64::INFINITY);
+        match config.hot_cold {
+            Some(bands) => println!("{}", bands.label(distance_km)),
+            None => println!("{}: {:.0} km", guess.name, distance_km),
+        }
+        if let Some(note) = fallback_note(cache, config) {
+            println!("{}", note);
+        }
+    }
+}
+
+/// What to do when Enter is pressed at the distance prompt without a distance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EmptyDistancePolicy {
+    /// Ask for the distance again.
+    Reprompt,
+    /// Drop the guess and go back to the country prompt.
+    Cancel,
+}
+
+impl std::str::FromStr for EmptyDistancePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "reprompt" => Ok(Self::Reprompt),
+            "cancel" => Ok(Self::Cancel),
+            _ => anyhow::bail!("Unknown empty-distance behaviour '{}'. Use reprompt or cancel", s),
+        }
+    }
+}
+
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Countries whose names are close to a mistyped `input`: within a couple of
+/// edits, or containing it, best matches first.
+fn suggest_countries<'a>(countries: &'a [CountryData], input: &str, limit: usize) -> Vec<&'a CountryData> {
+    let input = strip_invisible(input);
+    let input = unquote(input.trim()).to_lowercase();
+    if input.is_empty() {
+        return Vec::new();
+    }
+    let max_edits = (input.chars().count() / 3).clamp(1, 3);
+
+    let mut scored: Vec<(usize, &CountryData)> = countries.iter()
+        .filter_map(|country| {
+            let name = country.name.to_lowercase();
+            let edits = edit_distance(&input, &name);
+            if edits <= max_edits {
+                Some((edits, country))
+            } else if name.contains(&input) {
+                Some((max_edits + 1, country))
+            } else {
+                None
             }
-            Ok((distance, margin))
+        })
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    scored.into_iter().take(limit).map(|(_, country)| country).collect()
+}
+
+/// Levenshtein distance over characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Resolves the reply to a list of suggestions: a number picks that suggestion,
+/// anything else is looked up as a country name.
+fn pick_suggestion<'a>(
+    reply: &str,
+    suggestions: &[&'a CountryData],
+    countries: &'a [CountryData],
+) -> Result<&'a CountryData, String> {
+    let reply = reply.trim();
+    if reply.is_empty() {
+        return Err("No country chosen.".to_string());
+    }
+    if let Ok(number) = reply.parse::<usize>() {
+        return number.checked_sub(1)
+            .and_then(|index| suggestions.get(index))
+            .copied()
+            .ok_or_else(|| format!("Error: choose a number from 1 to {}", suggestions.len()));
+    }
+    find_country(countries, reply).ok_or_else(|| format!("Error: Country '{}' not found in database", reply))
+}
+
+/// Looks a country up by display or dataset name, ignoring case, invisible
+/// characters, surrounding quotes and a leading "The" on either side, so
+/// "The Gambia" finds "Gambia" and vice versa.
+fn find_country<'a>(countries: &'a [CountryData], name: &str) -> Option<&'a CountryData> {
+    let name = strip_invisible(name);
+    let name = without_article(unquote(name.trim()));
+    countries.iter().find(|c| {
+        without_article(&c.name).eq_ignore_ascii_case(name)
+            || without_article(&c.source_name).eq_ignore_ascii_case(name)
+    })
+}
+
+/// Removes soft hyphens, zero-width spaces and other invisible formatting
+/// characters that come along with copy-pasted names.
+fn strip_invisible(name: &str) -> std::borrow::Cow<'_, str> {
+    let invisible = |c: char| {
+        c.is_control()
+            || matches!(c, '\u{ad}' | '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2064}' | '\u{feff}')
+    };
+    if name.contains(invisible) {
+        std::borrow::Cow::Owned(name.chars().filter(|&c| !invisible(c)).collect())
+    } else {
+        std::borrow::Cow::Borrowed(name)
+    }
+}
+
+/// Strips one pair of matching quotes around a pasted name, leaving quotes
+/// inside it (as in "Côte d'Ivoire") alone.
+fn unquote(name: &str) -> &str {
+    const PAIRS: [(char, char); 4] = [('"', '"'), ('\'', '\''), ('“', '”'), ('‘', '’')];
+    PAIRS.iter()
+        .find_map(|&(open, close)| name.strip_prefix(open)?.strip_suffix(close))
+        .map_or(name, str::trim)
+}
+
+fn without_article(name: &str) -> &str {
+    match name.get(..4) {
+        Some(article) if article.eq_ignore_ascii_case("the ") => name[4..].trim_start(),
+        _ => name,
+    }
+}
+
+/// Prompts for the distance of a guess of `guess`, following
+/// `--on-empty-distance` when nothing is entered. `None` cancels the guess.
+fn read_distance(input: &mut dyn BufRead, echo: bool, guess: &str, config: &Config) -> io::Result<Option<String>> {
+    loop {
+        print!(
+            "Enter the distance (km) and optional margin (e.g., 500{d}50 or 500{d}0+50): ",
+            d = config.delimiter,
+        );
+        io::stdout().flush()?;
+        let mut distance_input = String::new();
+        let read = read_input(input, &mut distance_input, echo)?;
+        if !distance_input.trim().is_empty() {
+            return Ok(Some(distance_input));
+        }
+        // At the end of the input there is nothing to re-prompt for.
+        if read == 0 || config.on_empty_distance == EmptyDistancePolicy::Cancel {
+            println!("No distance entered; the guess of {} is cancelled.", guess);
+            return Ok(None);
+        }
+        println!("Please enter a distance, e.g. 500 or 500{}50.", config.delimiter);
+    }
+}
+
+/// Reads one line of input, echoing it when it comes from a batch file so the
+/// output reads like the interactive session would.
+fn read_input(input: &mut dyn BufRead, line: &mut String, echo: bool) -> io::Result<usize> {
+    let read = input.read_line(line)?;
+    if echo && read > 0 {
+        println!("{}", line.trim_end());
+    }
+    Ok(read)
+}
+
+/// Returns the text after `command` if the input is that command, so
+/// `cache list` yields `Some("list")` and `cache` yields `Some("")`.
+fn command_args<'a>(input: &'a str, command: &str) -> Option<&'a str> {
+    let (head, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    head.eq_ignore_ascii_case(command).then(|| rest.trim())
+}
+
+/// Runs the query, widening the margin step by step while fewer than the
+/// query's minimum number of candidates match. Returns the query as finally
+/// used along with its candidates.
+///
+/// Rather than searching once per step, the widest band the steps can reach is
+/// searched once: if nothing lies in it no step can help, and otherwise its
+/// distances show which step first has enough candidates.
+fn search_with_expansion(
+    guessed_country: &CountryData,
+    initial_query: DistanceInput,
+    countries: &[CountryData],
+    cache: &Arc<Mutex<DistanceCache>>,
+    config: &Config,
+    session: &Session,
+    progress: &SearchProgress,
+) -> (DistanceInput, Vec<Candidate>) {
+    let search = |query: &DistanceInput| {
+        find_mystery_countries(guessed_country, query, countries, Arc::clone(cache), config, session, progress)
+    };
+    let initial = search(&initial_query);
+    if initial.len() >= initial_query.min_candidates || progress.is_cancelled() {
+        return (initial_query, initial);
+    }
+
+    let mut steps = vec![initial_query];
+    while steps[steps.len() - 1].widest_margin_km() < MAX_MARGIN_INCREASE {
+        steps.push(steps[steps.len() - 1].widened(MARGIN_STEP));
+    }
+    if steps.len() == 1 {
+        return (initial_query, initial);
+    }
+    let widest = steps[steps.len() - 1];
+
+    let reachable = search(&widest);
+    // `--show-nearest-on-empty` lists the near misses itself.
+    if reachable.is_empty() && config.nearest_on_empty == 0 {
+        if let Some(nearest) = nearest_misses(guessed_country, &widest, countries, cache, config, session, 1).pop() {
+            println!(
+                "No country is within {} of {} km; the nearest is {} at {:.*} km.",
+                widest.describe_margin(config.precision),
+                widest.distance_km,
+                nearest.name,
+                config.precision,
+                nearest.distance_km,
+            );
         }
-        _ => anyhow::bail!("Invalid input format. Use 'distance' or 'distance--margin'")
+        return (widest, reachable);
+    }
+
+    // Special pairs match whatever the band, so they count at every step.
+    let fits = |step: &DistanceInput, candidate: &Candidate| {
+        step.contains(candidate.distance_km) || initial.iter().any(|c| c.name == candidate.name)
+    };
+    let count_at = |step: &DistanceInput| reachable.iter().filter(|c| fits(step, c)).count();
+    let mut chosen = steps.iter()
+        .position(|step| count_at(step) >= initial_query.min_candidates)
+        .unwrap_or(steps.len() - 1);
+    // Stop at the step before the first that matches too many; the initial
+    // query is what was typed, so it is never cut back.
+    let limit = config.max_expanded_candidates;
+    let first_over = steps[..=chosen].iter().position(|step| count_at(step) > limit);
+    if let Some(over) = first_over.filter(|&over| limit > 0 && over > 0) {
+        println!(
+            "Widening the margin to {} would match {} countries, over the limit of {}; stopping at {}. Try a tighter distance or another guess.",
+            steps[over].describe_margin(config.precision),
+            count_at(&steps[over]),
+            limit,
+            steps[over - 1].describe_margin(config.precision),
+        );
+        chosen = over - 1;
     }
+    let query = steps[chosen];
+    let candidates = reachable.into_iter().filter(|c| fits(&query, c)).collect();
+    (query, candidates)
+}
+
+/// The `count` countries whose distances from the guess come closest to the
+/// band, nearest first, for reporting a query nothing can match.
+fn nearest_misses(
+    guessed_country: &CountryData,
+    query: &DistanceInput,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+    session: &Session,
+    count: usize,
+) -> Vec<Candidate> {
+    let miss_km = |distance_km: f64| (query.lower_bound() - distance_km).max(distance_km - query.upper_bound());
+    let mut misses: Vec<Candidate> = countries.iter()
+        .filter(|country| country.name != guessed_country.name && session.allows(country))
+        .filter_map(|country| {
+            let distance_km = cached_distance_km(cache, guessed_country, country, config)?;
+            Some(Candidate { name: country.name.clone(), distance_km })
+        })
+        .collect();
+    misses.sort_by(|a, b| miss_km(a.distance_km).total_cmp(&miss_km(b.distance_km)).then_with(|| a.name.cmp(&b.name)));
+    misses.truncate(count);
+    misses
+}
+
+/// How far a search has got, shared between the thread running it and the
+/// one reporting on it, plus a flag asking it to stop early.
+#[derive(Debug, Default)]
+struct SearchProgress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    running: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+impl SearchProgress {
+    fn begin(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    fn finish(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Starts counting a pass over `total` countries.
+    fn start_pass(&self, total: usize) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    fn advance(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn percent(&self) -> usize {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0;
+        }
+        self.done.load(Ordering::Relaxed).min(total) * 100 / total
+    }
+
+    /// Asks a running search to stop. Returns false if none was running.
+    fn cancel(&self) -> bool {
+        let running = self.running.load(Ordering::SeqCst);
+        if running {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+        running
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs `search` on a worker thread, showing its percentage until it finishes
+/// or is cancelled.
+fn search_in_background<F>(search: F, progress: &SearchProgress) -> (DistanceInput, Vec<Candidate>)
+where
+    F: FnOnce() -> (DistanceInput, Vec<Candidate>) + Send,
+{
+    progress.begin();
+    let result = std::thread::scope(|scope| {
+        let worker = scope.spawn(search);
+        while !worker.is_finished() {
+            print!("\rSearching... {:>3}%", progress.percent());
+            let _ = io::stdout().flush();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        print!("\r{:20}\r", "");
+        worker.join()
+    });
+    progress.finish();
+    result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+/// A country that fits the query, with its computed distance from the guess.
+#[derive(Debug, Clone, PartialEq)]
+struct Candidate {
+    name: String,
+    distance_km: f64,
+}
+
+/// Models a query is re-run under by `--compare-models`.
+fn compared_models(ellipsoid: Ellipsoid) -> [(DistanceModel, &'static str); 3] {
+    [
+        (DistanceModel::GreatCircle, "border"),
+        (DistanceModel::Centroid, "centroid"),
+        (DistanceModel::Geodesic(ellipsoid), "geodesic"),
+    ]
+}
+
+/// Re-runs the final query under each of `compared_models` and lists every
+/// candidate with the models that find it, marking those they disagree on.
+fn model_comparison(
+    guess: &CountryData,
+    query: &DistanceInput,
+    countries: &[CountryData],
+    cache: &Arc<Mutex<DistanceCache>>,
+    config: &Config,
+    session: &Session,
+) -> String {
+    let progress = SearchProgress::default();
+    let models = compared_models(config.ellipsoid);
+    let results: Vec<Vec<Candidate>> = models.iter()
+        .map(|&(model, _)| {
+            let config = Config { model, ..config.clone() };
+            find_mystery_countries(guess, query, countries, Arc::clone(cache), &config, session, &progress)
+        })
+        .collect();
+
+    let mut names: Vec<&str> = results.iter().flatten().map(|candidate| candidate.name.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+    let labels: Vec<&str> = models.iter().map(|&(_, label)| label).collect();
+    let mut lines = vec![format!("Candidates by model ({}; * where they disagree):", labels.join(", "))];
+    if names.is_empty() {
+        lines.push("  none under any model".to_string());
+    }
+    for name in names {
+        let cells: Vec<String> = results.iter()
+            .map(|candidates| match candidates.iter().find(|candidate| candidate.name == name) {
+                Some(candidate) => format!("{:>9.*}", config.precision, candidate.distance_km),
+                None => format!("{:>9}", "-"),
+            })
+            .collect();
+        let found = results.iter().filter(|candidates| candidates.iter().any(|c| c.name == name)).count();
+        let marker = if found == results.len() { ' ' } else { '*' };
+        lines.push(format!("{} {} {}", marker, cells.join(" "), name));
+    }
+    lines.join("\n")
 }
 
 fn find_mystery_countries(
     guessed_country: &CountryData,
-    known_distance_km: f64,
-    margin_error_km: f64,
+    query: &DistanceInput,
     all_countries: &[CountryData],
     cache: Arc<Mutex<DistanceCache>>,
-) -> Vec<String> {
-    let lower_bound = known_distance_km - margin_error_km;
-    let upper_bound = known_distance_km + margin_error_km;
+    config: &Config,
+    session: &Session,
+    progress: &SearchProgress,
+) -> Vec<Candidate> {
+    let mut matches: Vec<Candidate> =
+        mystery_candidates(guessed_country, query, all_countries, &cache, config, session, progress).collect();
+
+    // Sorted so the output never depends on the order of the dataset.
+    matches.sort_by(|a, b| a.name.cmp(&b.name));
+    matches
+}
 
+/// The countries matching the query, in dataset order, each computed only when
+/// the iterator reaches it so callers can show matches as they are found.
+fn mystery_candidates<'a>(
+    guessed_country: &'a CountryData,
+    query: &'a DistanceInput,
+    all_countries: &'a [CountryData],
+    cache: &'a Mutex<DistanceCache>,
+    config: &'a Config,
+    session: &'a Session,
+    progress: &'a SearchProgress,
+) -> impl Iterator<Item = Candidate> + 'a {
+    progress.start_pass(all_countries.len());
     all_countries.iter()
+        .take_while(|_| !progress.is_cancelled())
+        .inspect(|_| progress.advance())
         .filter(|country| country.name != guessed_country.name)
+        .filter(|country| session.allows(country))
         .filter_map(|country| {
-            if is_special_case(&guessed_country.name, &country.name) {
-                return Some(country.name.clone());
+            let special = !config.no_special
+                && is_special_case(guessed_country, country);
+            if !special && hulls_rule_out(cache, guessed_country, country, query, config) {
+                return None;
             }
+            let distance_km = cached_distance_km(cache, guessed_country, country, config)?;
 
-            let mut cache_guard = cache.lock().ok()?;
-            let distance_km = cache_guard.get_or_calculate(
-                &guessed_country.name,
-                &country.name,
-                || Some(calculate_min_distance_km(&guessed_country.points, &country.points))
-            )?;
-            drop(cache_guard);
-
-            if distance_km >= lower_bound && distance_km <= upper_bound {
-                Some(country.name.clone())
+            if special || query.contains(distance_km) {
+                Some(Candidate { name: country.name.clone(), distance_km })
             } else {
                 None
             }
         })
-        .collect()
 }
 
-fn is_special_case(country1: &str, country2: &str) -> bool {
-    let special_pairs = [
-        ("South Africa", "Lesotho"),
-        ("Italy", "Vatican"),
-        ("Italy", "San Marino"),
-        ("France", "Monaco"),
-        ("Spain", "Gibraltar"),
-        ("China", "Hong Kong"),
-        ("China", "Macau"),
-    ];
-
-    special_pairs.iter().any(|&(a, b)| {
-        (country1.eq_ignore_ascii_case(a) && country2.eq_ignore_ascii_case(b)) ||
-            (country1.eq_ignore_ascii_case(b) && country2.eq_ignore_ascii_case(a))
+/// Whether the countries' convex hulls are already too far apart for the pair to
+/// reach the query's band, letting the full border scan be skipped. Pairs
+/// already cached are never pruned since their exact distance is free.
+fn hulls_rule_out(
+    cache: &Mutex<DistanceCache>,
+    country1: &CountryData,
+    country2: &CountryData,
+    query: &DistanceInput,
+    config: &Config,
+) -> bool {
+    let (Some(hull1), Some(hull2)) = (&country1.hull, &country2.hull) else {
+        return false;
+    };
+    if let (Some(circle1), Some(circle2)) = (&hull1.circle, &hull2.circle) {
+        if circle1.lower_bound_km(circle2, config.model) > query.upper_bound() {
+            return true;
+        }
+    }
+    let Ok(mut cache) = cache.lock() else {
+        return false;
+    };
+    if cache.contains(config.model, &country1.name, &country2.name) {
+        return false;
+    }
+
+    // Centroids lie inside their hulls, so the bound holds for every model
+    // built on the same point metric.
+    let base = config.model.base();
+    let lower_bound_km = cache.lower_bound_or_calculate(base, &country1.name, &country2.name, || {
+        hull_lower_bound_km(hull1, hull2, base)
+    });
+    lower_bound_km > query.upper_bound()
+}
+
+fn cached_distance_km(
+    cache: &Mutex<DistanceCache>,
+    country1: &CountryData,
+    country2: &CountryData,
+    config: &Config,
+) -> Option<f64> {
+    // A blend is cheap to rebuild from its two parts, so only those are cached
+    // and every weight shares them.
+    if let DistanceModel::Blend { border_weight_permille } = config.model {
+        let weight = f64::from(border_weight_permille) / 1000.0;
+        let border = Config { model: DistanceModel::GreatCircle, ..config.clone() };
+        let centroid = Config { model: DistanceModel::Centroid, ..config.clone() };
+        let border_km = cached_distance_km(cache, country1, country2, &border)?;
+        let centroid_km = cached_distance_km(cache, country1, country2, &centroid)?;
+        return Some(weight * border_km + (1.0 - weight) * centroid_km);
+    }
+
+    let mut cache_guard = cache.lock().ok()?;
+    if exceeds_vertex_limit(country1, country2, config) {
+        return cache_guard.fallback_or_calculate(
+            config.model,
+            &country1.name,
+            &country2.name,
+            || country_distance_km(country1, country2, config)
+        );
+    }
+    cache_guard.get_or_calculate(
+        config.model,
+        &country1.name,
+        &country2.name,
+        || country_distance_km(country1, country2, config)
+    )
+}
+
+/// Whether `--max-pair-vertices` has the pair measured between centroids.
+fn exceeds_vertex_limit(country1: &CountryData, country2: &CountryData, config: &Config) -> bool {
+    let vertices = country1.points.len() + country2.points.len();
+    config.model != DistanceModel::Centroid && config.max_pair_vertices.is_some_and(|limit| vertices > limit)
+}
+
+/// A note on the pairs measured between centroids since the last note, for
+/// printing once after each command.
+fn fallback_note(cache: &Mutex<DistanceCache>, config: &Config) -> Option<String> {
+    let count = cache.lock().ok()?.take_new_fallbacks();
+    (count > 0).then(|| {
+        format!(
+            "Note: {} pair{} measured between centroids (over --max-pair-vertices {})",
+            count,
+            if count == 1 { "" } else { "s" },
+            config.max_pair_vertices.unwrap_or_default(),
+        )
+    })
+}
+
+fn country_distance_km(country1: &CountryData, country2: &CountryData, config: &Config) -> Option<f64> {
+    if config.model == DistanceModel::Centroid {
+        let (centroid1, centroid2) = (country1.geometry.centroid()?, country2.geometry.centroid()?);
+        return Some(centroid1.haversine_distance(&centroid2) / 1000.0);
+    }
+
+    if exceeds_vertex_limit(country1, country2, config) {
+        let (centroid1, centroid2) = (country1.geometry.centroid()?, country2.geometry.centroid()?);
+        return Some(config.model.point_distance_m(&centroid1, &centroid2) / 1000.0);
+    }
+
+    Some(if config.vertex_only {
+        calculate_min_distance_km(&country1.points, &country2.points, config.model)
+    } else {
+        calculate_edge_distance_km(country1.border(), country2.border(), config.model)
+    })
+}
+
+/// The candidate whose distance sits closest to the centre of the band, the one
+/// most likely to survive a small error in the entered distance. Once earlier
+/// guesses leave `remaining`, only candidates still in it are considered.
+fn recommend_candidate<'a>(
+    candidates: &'a [Candidate],
+    query: &DistanceInput,
+    remaining: Option<&[String]>,
+) -> Option<&'a Candidate> {
+    let centre_km = query.centre_km();
+    candidates.iter()
+        .filter(|candidate| remaining.is_none_or(|remaining| remaining.contains(&candidate.name)))
+        .min_by(|a, b| {
+            let deviation_a = (a.distance_km - centre_km).abs();
+            let deviation_b = (b.distance_km - centre_km).abs();
+            deviation_a.total_cmp(&deviation_b).then_with(|| a.name.cmp(&b.name))
+        })
+}
+
+/// Enclaves and their surrounding country, matched by dataset name.
+const SPECIAL_PAIRS: [(&str, &str); 7] = [
+    ("South Africa", "Lesotho"),
+    ("Italy", "Vatican"),
+    ("Italy", "San Marino"),
+    ("France", "Monaco"),
+    ("Spain", "Gibraltar"),
+    ("China", "Hong Kong"),
+    ("China", "Macau"),
+];
+
+/// Takes loaded countries rather than names, so a pair only fires when both
+/// of its members are in the data.
+fn is_special_case(country1: &CountryData, country2: &CountryData) -> bool {
+    let (name1, name2) = (&country1.source_name, &country2.source_name);
+    SPECIAL_PAIRS.iter().any(|&(a, b)| {
+        (name1.eq_ignore_ascii_case(a) && name2.eq_ignore_ascii_case(b)) ||
+            (name1.eq_ignore_ascii_case(b) && name2.eq_ignore_ascii_case(a))
     })
 }
 
-fn extract_points(country: &Feature) -> Option<Vec<Point<f64>>> {
-    let geometry = country.geometry.as_ref()?;
-    let mut points = Vec::with_capacity(100);
+/// Special pairs with only one member in the data, which can never fire; a
+/// sign the dataset spells the other one differently.
+fn half_present_special_pairs(countries: &[CountryData]) -> Vec<String> {
+    let present = |name: &str| countries.iter().any(|country| country.source_name.eq_ignore_ascii_case(name));
+    SPECIAL_PAIRS.iter()
+        .filter(|&&(a, b)| present(a) != present(b))
+        .map(|&(a, b)| format!("{} and {} (no {})", a, b, if present(a) { b } else { a }))
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{country, fixture_countries, names, square, Solver};
+    #[test]
+    fn fixture_distances_match_the_known_answers() {
+        let solver = Solver::fixture();
+        let km = |to| format!("{:.1}", solver.distance_km("Alpha", to));
+        assert_eq!(km("Gamma"), "0.0");
+        assert_eq!(km("Beta"), "111.2");
+        assert_eq!(km("Delta Islands"), "1000.6");
+        assert_eq!(km("Antipodia"), "19814.7");
+    }
+
+    #[test]
+    fn fixture_query_finds_the_country_in_the_band() {
+        let solver = Solver::fixture();
+        assert_eq!(names(&solver.search("Alpha", "1000--50")), ["Delta Islands"]);
+    }
+
+    #[test]
+    fn hand_made_countries_narrow_over_guesses() {
+        let big = country("Big", &[&[(5.0, 0.0), (7.0, 0.0), (7.0, 2.0), (5.0, 2.0), (5.0, 0.0)]]);
+        let mut solver = Solver::new(vec![square("West", 0.0, 0.0, 1.0), square("East", 2.0, 0.0, 1.0), big])
+            .with_config(|config| config.max_expanded_candidates = 0);
+        let far = format!("{:.0}--0+1000", solver.distance_km("West", "East"));
+        assert_eq!(solver.guess("West", &far), ["Big", "East"]);
+        let near = format!("{:.0}--10", solver.distance_km("Big", "East"));
+        assert_eq!(solver.guess("Big", &near), ["East"]);
+        assert_eq!(solver.remaining(), ["East"]);
+    }
+
+    #[test]
+    fn shuffled_data_gives_the_same_sorted_candidates() {
+        let solver = Solver::fixture();
+        for seed in [1, 2, 3] {
+            let mut countries = fixture_countries();
+            SplitMix64::new(seed).shuffle(&mut countries);
+            let shuffled = Solver::new(countries);
+            for (guess, distance) in [("Alpha", "500--600"), ("Beta", "20000--20000"), ("Antipodia", "19000--1000")] {
+                assert_eq!(names(&shuffled.search(guess, distance)), names(&solver.search(guess, distance)));
+            }
+        }
+    }
+
+    #[test]
+    fn recommend_picks_the_candidate_nearest_the_band_centre() {
+        let candidate = |name: &str, distance_km| Candidate { name: name.to_string(), distance_km };
+        let candidates = [candidate("Low", 500.0), candidate("Middle", 522.0), candidate("High", 549.0)];
+        let solver = Solver::fixture();
+        // The band is [500, 550], so its centre is 525, not the typed 500.
+        let best = recommend_candidate(&candidates, &solver.query("500--0+50"), None).unwrap();
+        assert_eq!(best.name, "Middle");
+        let best = recommend_candidate(&candidates, &solver.query("500--10"), None).unwrap();
+        assert_eq!(best.name, "Low");
+    }
+
+    #[test]
+    fn recommend_skips_candidates_earlier_guesses_ruled_out() {
+        let mut solver = Solver::fixture();
+        solver.guess("Alpha", "1000--50");
+        let candidates = solver.search("Beta", "111--1000");
+        assert_eq!(recommend_candidate(&candidates, &solver.query("111--1000"), None).unwrap().name, "Gamma");
+        let remaining = solver.session.remaining.as_deref();
+        let best = recommend_candidate(&candidates, &solver.query("111--1000"), remaining).unwrap();
+        assert_eq!(best.name, "Delta Islands");
+    }
+
+    #[test]
+    fn hot_cold_hints_follow_the_bands() {
+        let solver = Solver::fixture();
+        let bands: HotColdBands = "500,2000".parse().unwrap();
+        assert_eq!(bands.label(solver.distance_km("Alpha", "Beta")), "hot");
+        assert_eq!(bands.label(solver.distance_km("Alpha", "Delta Islands")), "warm");
+        assert_eq!(bands.label(solver.distance_km("Alpha", "Antipodia")), "cold");
+        // Each limit belongs to the cooler band.
+        assert_eq!((bands.label(500.0), bands.label(2000.0)), ("warm", "cold"));
+        assert!("2000,500".parse::<HotColdBands>().is_err());
+    }
 
-    match &geometry.value {
-        Value::MultiPolygon(coords) => {
-            for polygon in coords {
-                for ring in polygon {
-                    points.extend(ring.iter().map(|coord| Point::new(coord[0], coord[1])));
+    #[test]
+    fn hull_pruning_never_changes_the_results() {
+        let hook: &[(f64, f64)] = &[(0.0, 0.0), (6.0, 0.0), (6.0, 1.0), (1.0, 1.0), (1.0, 5.0), (6.0, 5.0), (6.0, 6.0), (0.0, 6.0), (0.0, 0.0)];
+        let countries = vec![
+            // Inside Hook's bay, so their hulls overlap without the borders meeting.
+            country("Hook", &[hook]),
+            square("Bay", 3.0, 2.5, 1.0),
+            square("Near", 9.0, 0.0, 2.0),
+            square("North", 20.0, 70.0, 5.0),
+            square("South", -60.0, -40.0, 3.0),
+            country("Dateline", &[
+                &[(178.0, 10.0), (180.0, 10.0), (180.0, 12.0), (178.0, 12.0), (178.0, 10.0)],
+                &[(-180.0, 10.0), (-178.0, 10.0), (-178.0, 12.0), (-180.0, 12.0), (-180.0, 10.0)],
+            ]),
+            square("Far Side", -177.0, -5.0, 2.0),
+        ];
+        let unpruned: Vec<CountryData> = countries.iter().cloned().map(|mut c| { c.hull = None; c }).collect();
+        let models = [
+            DistanceModel::GreatCircle,
+            DistanceModel::Rhumb,
+            DistanceModel::Chord,
+            DistanceModel::Geodesic(Ellipsoid::WGS84),
+            DistanceModel::Centroid,
+            DistanceModel::blend(0.5).unwrap(),
+        ];
+        for model in models {
+            for vertex_only in [false, true] {
+                let settings = |config: &mut Config| { config.model = model; config.vertex_only = vertex_only; };
+                let pruned = Solver::new(countries.clone()).with_config(settings);
+                let full = Solver::new(unpruned.clone()).with_config(settings);
+                for guess in &countries {
+                    for other in countries.iter().filter(|other| other.name != guess.name) {
+                        // A band that just reaches the true distance: any bound
+                        // above it would drop `other`.
+                        let exact = full.distance_km(&guess.name, &other.name).to_string();
+                        let expected = full.search(&guess.name, &exact);
+                        assert!(names(&expected).contains(&other.name));
+                        assert_eq!(pruned.search(&guess.name, &exact), expected, "{:?} from {} to {}", model, guess.name, other.name);
+                    }
                 }
+                // The bounds are tight enough to prune something at all.
+                let fresh = Solver::new(countries.clone()).with_config(settings);
+                let near = fresh.query(&full.distance_km("Hook", "Near").to_string());
+                assert!(hulls_rule_out(&fresh.cache, fresh.country("Hook"), fresh.country("North"), &near, &fresh.config), "{:?}", model);
             }
         }
-        Value::Polygon(coords) => {
-            for ring in coords {
-                points.extend(ring.iter().map(|coord| Point::new(coord[0], coord[1])));
+    }
+
+    #[test]
+    fn a_suggestion_is_picked_by_its_number() {
+        let solver = Solver::new(vec![square("Niger", 0.0, 0.0, 1.0), square("Nigeria", 2.0, 0.0, 1.0), square("Chad", 4.0, 0.0, 1.0)]);
+        let suggestions = suggest_countries(&solver.countries, "Nige", MAX_SUGGESTIONS);
+        let listed: Vec<&str> = suggestions.iter().map(|country| country.name.as_str()).collect();
+        assert_eq!(listed, ["Niger", "Nigeria"]);
+
+        let pick = |reply| pick_suggestion(reply, &suggestions, &solver.countries).map(|country| country.name.as_str());
+        assert_eq!(pick(" 2\n"), Ok("Nigeria"));
+        assert_eq!(pick("1"), Ok("Niger"));
+        // Not on the list, so the reply is taken as a name.
+        assert_eq!(pick("chad"), Ok("Chad"));
+        for out_of_range in ["0", "3"] {
+            assert_eq!(pick(out_of_range), Err("Error: choose a number from 1 to 2".to_string()));
+        }
+        assert!(pick("").is_err());
+    }
+
+    #[test]
+    fn no_special_filters_enclaves_by_their_actual_distance() {
+        // Deliberately far apart, so only the override could match the band.
+        let countries = vec![square("Italy", 10.0, 40.0, 4.0), square("Vatican", 20.0, 40.0, 0.1), square("Malta", 14.0, 35.0, 0.5)];
+        let special = Solver::new(countries.clone());
+        let raw = Solver::new(countries).with_config(|config| config.no_special = true);
+
+        assert_eq!(names(&special.search("Italy", "0--10")), ["Vatican"]);
+        assert!(special.search("Italy", "0--10")[0].distance_km > 10.0);
+        assert!(raw.search("Italy", "0--10").is_empty());
+        let actual = raw.distance_km("Italy", "Vatican");
+        assert_eq!(names(&raw.search("Italy", &format!("{}--1", actual))), ["Vatican"]);
+    }
+
+    #[test]
+    fn a_special_pair_with_a_missing_member_never_matches() {
+        // The data spells Vatican as "Holy See", so Italy–Vatican can't fire.
+        let solver = Solver::new(vec![
+            square("Italy", 10.0, 40.0, 4.0),
+            square("Holy See", 20.0, 40.0, 0.1),
+            square("San Marino", 25.0, 40.0, 0.1),
+            square("Malta", 14.0, 35.0, 0.5),
+        ]);
+        assert_eq!(names(&solver.search("Italy", "0--10")), ["San Marino"]);
+        assert!(!is_special_case(solver.country("Italy"), solver.country("Holy See")));
+        assert_eq!(half_present_special_pairs(&solver.countries), ["Italy and Vatican (no Vatican)"]);
+
+        let lone = Solver::new(vec![square("Vatican", 20.0, 40.0, 0.1), square("Malta", 14.0, 35.0, 0.5)]);
+        assert!(lone.search("Vatican", "0--10").is_empty());
+        assert_eq!(half_present_special_pairs(&lone.countries), ["Italy and Vatican (no Italy)"]);
+    }
+
+    #[test]
+    fn an_even_blend_is_the_mean_of_border_and_centroid_distances() {
+        let config = parse_args(["--blend", "0.5"].into_iter().map(String::from)).unwrap();
+        assert_eq!(config.model, DistanceModel::blend(0.5).unwrap());
+        let blend = Solver::fixture().with_config(|c| c.model = config.model);
+        let border = Solver::fixture();
+        let centroid = Solver::fixture().with_config(|c| c.model = DistanceModel::Centroid);
+
+        for other in ["Beta", "Delta Islands", "Antipodia"] {
+            let mean_km = (border.distance_km("Alpha", other) + centroid.distance_km("Alpha", other)) / 2.0;
+            assert!((blend.distance_km("Alpha", other) - mean_km).abs() < 1e-9, "{}", other);
+        }
+        // Only the two parts are cached, so any other weight reuses them.
+        let cache = blend.cache.lock().unwrap();
+        assert_eq!(cache.len(), 6);
+        assert!(cache.contains(DistanceModel::Centroid, "Alpha", "Beta") && !cache.contains(config.model, "Alpha", "Beta"));
+    }
+
+    #[test]
+    fn cancelling_stops_a_background_search() {
+        let solver = Solver::new((0..50).map(|i| square(&format!("C{:02}", i), i as f64 * 2.0, 0.0, 1.0)).collect());
+        let query = solver.query("20000--20000");
+        let progress = SearchProgress::default();
+        let search = |cancel_after: usize| {
+            let mut found = Vec::new();
+            let candidates = mystery_candidates(solver.country("C00"), &query, &solver.countries, &solver.cache, &solver.config, &solver.session, &progress);
+            for candidate in candidates {
+                found.push(candidate);
+                if found.len() == cancel_after {
+                    assert!(progress.cancel(), "the search is running");
+                }
             }
+            (query, found)
+        };
+
+        let (_, found) = search_in_background(|| search(3), &progress);
+        assert_eq!(found.len(), 3);
+        assert!(progress.is_cancelled() && progress.percent() < 100);
+        assert!(!progress.cancel(), "nothing is running once it returns");
+
+        // The next search starts afresh.
+        let (_, found) = search_in_background(|| search(usize::MAX), &progress);
+        assert_eq!(found.len(), 49);
+        assert!(!progress.is_cancelled());
+        assert_eq!(progress.percent(), 100);
+    }
+
+    #[test]
+    fn a_leading_article_is_ignored_on_either_side() {
+        let solver = Solver::new(vec![square("Gambia", 0.0, 0.0, 1.0), square("The Bahamas", 2.0, 0.0, 1.0), square("Theland", 4.0, 0.0, 1.0)]);
+        assert_eq!(solver.country("The Gambia").name, "Gambia");
+        assert_eq!(solver.country("the  gambia").name, "Gambia");
+        assert_eq!(solver.country("Bahamas").name, "The Bahamas");
+        assert_eq!(solver.country("\"The Bahamas\"").name, "The Bahamas");
+        // Only a separate word counts as the article.
+        assert!(find_country(&solver.countries, "land").is_none());
+        assert_eq!(solver.country("Theland").name, "Theland");
+    }
+
+    #[test]
+    fn a_candidate_count_widens_the_margin_until_enough_match() {
+        let mut countries = vec![square("Home", 0.0, 0.0, 1.0)];
+        for (name, gap) in [("A", 4.5), ("B", 4.7), ("C", 5.1), ("D", 5.6)] {
+            countries.push(square(name, 1.0 + gap, 0.0, 1.0));
         }
-        _ => return None,
+        let mut solver = Solver::new(countries);
+        let target_km = solver.distance_km("Home", "A");
+        let typed = format!("{}--n3", target_km);
+        assert_eq!(solver.query(&typed).min_candidates, 3);
+
+        assert_eq!(solver.guess("Home", &typed), ["A", "B", "C"]);
+        let query = solver.session.constraints[0].query;
+        // Widened a kilometre at a time, stopping at the first margin that reaches C.
+        let needed_km = solver.distance_km("Home", "C") - target_km;
+        assert!(query.upper_margin_km >= needed_km && query.upper_margin_km - 1.0 < needed_km, "{:?}", query);
+        assert_eq!(query.lower_margin_km, query.upper_margin_km);
     }
-    Some(points)
-}
 
-fn calculate_min_distance_km(points1: &[Point<f64>], points2: &[Point<f64>]) -> f64 {
-    points1.iter()
-        .flat_map(|p1| points2.iter().map(move |p2| p1.haversine_distance(p2)))
-        .fold(f64::INFINITY, f64::min) / 1000.0
-}
\ No newline at end of file
+    #[test]
+    fn bounding_circles_never_exceed_the_true_distance() {
+        let countries = vec![
+            // Either side of the antimeridian, touching across it.
+            square("Date East", 179.0, -17.0, 1.0),
+            square("Date West", -180.0, -17.5, 1.5),
+            square("Samoa", -172.0, -14.0, 1.0),
+            square("Arctic", 100.0, 80.0, 8.0),
+            square("Tundra", -80.0, 78.0, 6.0),
+            square("Home", 0.0, 0.0, 3.0),
+            // Nearly opposite Home.
+            square("Opposite", -179.5, -3.5, 2.0),
+        ];
+        let models = [DistanceModel::GreatCircle, DistanceModel::Chord, DistanceModel::Geodesic(Ellipsoid::WGS84), DistanceModel::Rhumb];
+        for model in models {
+            let solver = Solver::new(countries.clone()).with_config(|config| config.model = model);
+            let mut pruning = 0;
+            for a in &solver.countries {
+                for b in solver.countries.iter().filter(|b| b.name != a.name) {
+                    let circle = |country: &CountryData| country.hull.as_ref().and_then(|hull| hull.circle.clone()).unwrap();
+                    let bound_km = circle(a).lower_bound_km(&circle(b), model);
+                    let true_km = solver.distance_km(&a.name, &b.name);
+                    assert!(bound_km <= true_km + 1e-9, "{:?}: {} to {} bounded at {} but {} apart", model, a.name, b.name, bound_km, true_km);
+                    if bound_km > 0.0 {
+                        pruning += 1;
+                    }
+                }
+            }
+            assert!(pruning > 0, "{:?} circles bound nothing", model);
+        }
+    }
+
+    #[test]
+    fn a_fractional_final_margin_is_reported_to_the_precision() {
+        let solver = Solver::fixture();
+        let progress = SearchProgress::default();
+        let expand = |typed: &str| {
+            search_with_expansion(solver.country("Alpha"), solver.query(typed), &solver.countries, &solver.cache, &solver.config, &solver.session, &progress)
+        };
+        // Delta Islands, 1000.6 km away, comes in after one 1 km step.
+        let (query, candidates) = expand("1000--0.1");
+        assert_eq!(names(&candidates), ["Delta Islands"]);
+        assert_eq!(query.describe_margin(1), "1.1 km");
+        assert_eq!(query.describe_margin(3), "1.1 km");
+        assert_eq!(query.describe_margin(0), "1 km");
+
+        let (query, _) = expand("1000--0.1+0.35");
+        assert_eq!(query.describe_margin(2), "-1.1 / +1.35 km");
+        assert_eq!(query.describe_margin(1), "-1.1 / +1.4 km");
+    }
+
+    #[test]
+    fn expansion_stops_before_the_candidate_cap_is_exceeded() {
+        // A row of specks about 0.56 km apart, all due east of Home.
+        let mut countries = vec![square("Home", -0.5, -0.5, 1.0)];
+        countries.extend((0..10).map(|k| square(&format!("Speck {}", k), 4.5 + 0.005 * k as f64, -0.0005, 0.001)));
+        let capped = Solver::new(countries.clone()).with_config(|config| config.max_expanded_candidates = 3);
+        let typed = format!("{}--n10", capped.distance_km("Home", "Speck 0"));
+        let progress = SearchProgress::default();
+        let expand = |solver: &Solver| {
+            search_with_expansion(solver.country("Home"), solver.query(&typed), &solver.countries, &solver.cache, &solver.config, &solver.session, &progress)
+        };
+
+        // At 2 km either side four specks match, so it stops at 1 km.
+        let (query, candidates) = expand(&capped);
+        assert_eq!(names(&candidates), ["Speck 0", "Speck 1"]);
+        assert_eq!(query.widest_margin_km(), 1.0);
+
+        let uncapped = Solver::new(countries).with_config(|config| config.max_expanded_candidates = 0);
+        let (query, candidates) = expand(&uncapped);
+        assert_eq!(candidates.len(), 10);
+        assert!(query.widest_margin_km() > 4.0);
+    }
+
+    #[test]
+    fn an_impossible_query_goes_straight_to_the_widest_margin() {
+        let solver = Solver::fixture();
+        let progress = SearchProgress::default();
+        let typed = solver.query("15000--10");
+        let (query, candidates) =
+            search_with_expansion(solver.country("Alpha"), typed, &solver.countries, &solver.cache, &solver.config, &solver.session, &progress);
+        assert!(candidates.is_empty());
+        assert_eq!(query.describe_margin(1), format!("{} km", MAX_MARGIN_INCREASE));
+
+        let nearest = nearest_misses(solver.country("Alpha"), &query, &solver.countries, &solver.cache, &solver.config, &solver.session, 2);
+        assert_eq!(names(&nearest), ["Antipodia", "Delta Islands"]);
+        // However far the margin widens, each pair is measured once.
+        assert_eq!(solver.cache.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn the_batch_summary_adds_up_the_queries() {
+        let mut solver = Solver::fixture();
+        let mut summary = BatchSummary::default();
+        for (guess, distance) in [("Alpha", "1000--50"), ("Beta", "5000--1"), ("Gamma", "10000--10000")] {
+            let candidates = solver.guess(guess, distance);
+            summary.record(candidates.len(), std::time::Duration::from_millis(250));
+        }
+        assert_eq!((summary.queries, summary.with_candidates, summary.candidates), (3, 2, 3));
+        assert_eq!(
+            summary.describe(),
+            "Batch summary\n-------------\nQueries: 3\nWith candidates: 2\nAverage candidates per query: 1.0\nSearch time: 0.75 s",
+        );
+        assert!(BatchSummary::default().describe().contains("Average candidates per query: 0.0"));
+    }
+
+    #[test]
+    fn surrounding_quotes_are_stripped_but_apostrophes_kept() {
+        let solver = Solver::new(vec![square("South Africa", 0.0, 0.0, 1.0), square("Côte d'Ivoire", 2.0, 0.0, 1.0)]);
+        for typed in ["\"South Africa\"", "'South Africa'", "“South Africa”", " \" south africa \" "] {
+            assert_eq!(solver.country(typed).name, "South Africa", "{}", typed);
+        }
+        assert_eq!(solver.country("Côte d'Ivoire").name, "Côte d'Ivoire");
+        assert_eq!(solver.country("'Côte d'Ivoire'").name, "Côte d'Ivoire");
+        // Unmatched quotes are part of the name.
+        assert!(find_country(&solver.countries, "\"South Africa").is_none());
+        assert!(find_country(&solver.countries, "\"South Africa'").is_none());
+    }
+
+    #[test]
+    fn the_model_comparison_marks_candidates_the_models_disagree_on() {
+        let solver = Solver::fixture();
+        let query = solver.query("0--5");
+        let comparison = model_comparison(solver.country("Alpha"), &query, &solver.countries, &solver.cache, &solver.config, &solver.session);
+        // Gamma touches Alpha, but their centres are a degree apart.
+        assert_eq!(comparison, [
+            "Candidates by model (border, centroid, geodesic; * where they disagree):",
+            "*       0.0         -       0.0 Gamma",
+        ].join("\n"));
+
+        let query = solver.query("10000--10000");
+        let comparison = model_comparison(solver.country("Alpha"), &query, &solver.countries, &solver.cache, &solver.config, &solver.session);
+        assert!(comparison.lines().skip(1).all(|line| line.starts_with(' ')), "{}", comparison);
+    }
+
+    #[test]
+    fn an_empty_distance_reprompts_or_cancels_instead_of_failing_to_parse() {
+        let reprompt = Config::default();
+        assert_eq!(reprompt.on_empty_distance, EmptyDistancePolicy::Reprompt);
+        let read = |typed: &str, config: &Config| read_distance(&mut typed.as_bytes(), false, "Alpha", config).unwrap();
+        assert_eq!(read("\n  \n500--50\n", &reprompt).as_deref(), Some("500--50\n"));
+        // The end of the input cancels rather than prompting forever.
+        assert_eq!(read("\n", &reprompt), None);
+
+        let cancel = Config { on_empty_distance: EmptyDistancePolicy::Cancel, ..Config::default() };
+        assert_eq!(read("\n500\n", &cancel), None);
+        assert_eq!(read("500\n", &cancel).as_deref(), Some("500\n"));
+    }
+
+    #[test]
+    fn a_seed_replays_the_same_hidden_country_and_shuffle() {
+        let countries = fixture_countries();
+        let shuffled = |seed: u64| {
+            let mut names: Vec<String> = countries.iter().map(|country| country.name.clone()).collect();
+            SplitMix64::new(seed).shuffle(&mut names);
+            names
+        };
+        for seed in [0, 7, 1_234_567_890] {
+            assert_eq!(hidden_country(&countries, seed).name, hidden_country(&countries, seed).name);
+            assert_eq!(shuffled(seed), shuffled(seed));
+        }
+        // Different seeds do pick different rounds.
+        let hidden: std::collections::HashSet<&str> = (0..20).map(|seed| hidden_country(&countries, seed).name.as_str()).collect();
+        assert!(hidden.len() > 1);
+        assert_ne!(shuffled(1), shuffled(2));
+    }
+
+    #[test]
+    fn pasted_invisible_characters_do_not_break_matching() {
+        let solver = Solver::fixture();
+        for pasted in ["Al\u{200b}pha", "\u{feff}Alpha", "Del\u{ad}ta Is\u{2060}lands", "Beta\u{200e}\r"] {
+            assert!(find_country(&solver.countries, pasted).is_some(), "{:?}", pasted);
+        }
+        assert_eq!(strip_invisible("Al\u{200b}pha"), "Alpha");
+        assert!(matches!(strip_invisible("Côte d'Ivoire"), std::borrow::Cow::Borrowed(_)));
+        assert!(suggest_countries(&solver.countries, "Alp\u{200b}ga", MAX_SUGGESTIONS).iter().any(|country| country.name == "Alpha"));
+    }
+
+    #[test]
+    fn an_empty_result_lists_the_nearest_misses_by_deviation() {
+        let solver = Solver::fixture();
+        assert!(solver.search("Alpha", "500--10").is_empty());
+        let query = solver.query("500--10");
+        let misses = |count| {
+            names(&nearest_misses(solver.country("Alpha"), &query, &solver.countries, &solver.cache, &solver.config, &solver.session, count))
+        };
+        // Beta is 378.8 km short of the band, Gamma 490 km short, Delta Islands 490.6 km over.
+        assert_eq!(misses(3), ["Beta", "Gamma", "Delta Islands"]);
+        assert_eq!(misses(1), ["Beta"]);
+        assert_eq!(misses(10).len(), 4);
+    }
+
+    #[test]
+    fn the_header_names_the_active_model() {
+        let config = Config::default();
+        assert_eq!(metric_header(&config), "Using nearest-border great-circle distance (what Globle shows; change with --model)");
+        let centroid = Config { model: DistanceModel::Centroid, ..config.clone() };
+        assert_eq!(
+            metric_header(&centroid),
+            "Using great-circle distance between centroids (Globle uses nearest-border great-circle distance)",
+        );
+        let vertices = Config { model: DistanceModel::Chord, vertex_only: true, ..config };
+        assert!(metric_header(&vertices).starts_with("Using nearest-vertex straight-line distance through the Earth"));
+    }
+
+    #[test]
+    fn a_huge_polygon_falls_back_to_the_centroid_distance() {
+        let circle: Vec<(f64, f64)> = (0..=2000)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * (i % 2000) as f64 / 2000.0;
+                (5.0 * angle.cos(), 5.0 * angle.sin())
+            })
+            .collect();
+        let countries = vec![country("Roundland", &[&circle]), square("Neighbour", 10.0, -0.5, 1.0)];
+        let exact = Solver::new(countries.clone());
+        let capped = Solver::new(countries).with_config(|config| config.max_pair_vertices = Some(100));
+
+        let border_km = exact.distance_km("Roundland", "Neighbour");
+        let fallback_km = capped.distance_km("Roundland", "Neighbour");
+        let (centroid1, centroid2) = (capped.country("Roundland").geometry.centroid().unwrap(), capped.country("Neighbour").geometry.centroid().unwrap());
+        assert!((fallback_km - centroid1.haversine_distance(&centroid2) / 1000.0).abs() < 1e-6);
+        assert!(fallback_km > border_km + 500.0, "{} vs {}", fallback_km, border_km);
+        // The stand-in is recorded as one, apart from the cache that gets saved.
+        assert_eq!(capped.cache.lock().unwrap().fallback_count(), 1);
+        assert_eq!(exact.cache.lock().unwrap().fallback_count(), 0);
+        assert_eq!(capped.cache.lock().unwrap().len(), 0);
+        assert_eq!(exact.cache.lock().unwrap().len(), 1);
+        // It is noted once, however often the pair is looked up.
+        capped.distance_km("Neighbour", "Roundland");
+        assert_eq!(
+            fallback_note(&capped.cache, &capped.config).as_deref(),
+            Some("Note: 1 pair measured between centroids (over --max-pair-vertices 100)"),
+        );
+        assert_eq!(fallback_note(&capped.cache, &capped.config), None);
+        assert_eq!(fallback_note(&exact.cache, &exact.config), None);
+    }
+
+    #[test]
+    fn generating_an_empty_dataset_is_refused() {
+        let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+        let error = parse(&["--generate-dataset", "0", "out.json"]).unwrap_err().to_string();
+        assert_eq!(error, "--generate-dataset needs at least one country; an empty dataset can't be loaded");
+        assert_eq!(parse(&["--generate-dataset", "3", "out.json"]).unwrap().generate_dataset, Some((3, "out.json".to_string())));
+    }
+}
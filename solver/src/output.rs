@@ -0,0 +1,429 @@
+//! How candidates are ordered, grouped and printed.
+
+use std::collections::HashMap;
+use geo::prelude::*;
+use anyhow::Result;
+use crate::{Candidate, Config, CountryData};
+use crate::query::DistanceInput;
+
+/// How candidates are ordered for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SortOrder {
+    Name,
+    Distance,
+    /// Closest to the entered distance first.
+    Deviation,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "name" => Ok(Self::Name),
+            "distance" => Ok(Self::Distance),
+            "deviation" => Ok(Self::Deviation),
+            _ => anyhow::bail!("Unknown sort order '{}'. Use name, distance or deviation", s),
+        }
+    }
+}
+
+/// How candidates that are equal under the sort order are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tiebreak {
+    Alpha,
+    /// Largest country first.
+    Area,
+    /// Most populous first; countries without a population estimate go last.
+    Population,
+}
+
+impl std::str::FromStr for Tiebreak {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "alpha" | "name" => Ok(Self::Alpha),
+            "area" => Ok(Self::Area),
+            "population" => Ok(Self::Population),
+            _ => anyhow::bail!("Unknown tiebreak '{}'. Use alpha, area or population", s),
+        }
+    }
+}
+
+impl Tiebreak {
+    /// Sort key placing the preferred country first; `None` for alphabetical.
+    fn key(self, country: &CountryData) -> Option<f64> {
+        match self {
+            Self::Alpha => None,
+            Self::Area => Some(-country.geometry.chamberlain_duquette_unsigned_area()),
+            Self::Population => Some(country.metadata.population.map_or(f64::INFINITY, |population| -population)),
+        }
+    }
+}
+
+/// Sorts candidates for display and returns, per candidate, whether its sort
+/// key equals a neighbour's at the printed precision, so an arbitrary order
+/// between equals isn't mistaken for a ranking. Equals are ordered by `tiebreak`.
+pub(crate) fn order_candidates(
+    candidates: &mut [Candidate],
+    order: SortOrder,
+    tiebreak: Tiebreak,
+    query: &DistanceInput,
+    precision: usize,
+    countries: &[CountryData],
+) -> Vec<bool> {
+    let scale = 10f64.powi(precision as i32);
+    let key = |c: &Candidate| match order {
+        SortOrder::Name => None,
+        SortOrder::Distance => Some((c.distance_km * scale).round()),
+        SortOrder::Deviation => Some(((c.distance_km - query.distance_km).abs() * scale).round()),
+    };
+
+    let secondary: HashMap<&str, f64> = candidates.iter()
+        .filter_map(|c| {
+            let country = countries.iter().find(|country| country.name == c.name)?;
+            Some((country.name.as_str(), tiebreak.key(country)?))
+        })
+        .collect();
+    let secondary = |c: &Candidate| secondary.get(c.name.as_str()).copied().unwrap_or(0.0);
+
+    // Stable, so remaining ties keep the alphabetical order `find_mystery_countries` returns.
+    candidates.sort_by(|a, b| match (key(a), key(b)) {
+        (Some(x), Some(y)) => x.total_cmp(&y).then_with(|| secondary(a).total_cmp(&secondary(b))),
+        _ => a.name.cmp(&b.name),
+    });
+
+    (0..candidates.len())
+        .map(|i| {
+            let this = key(&candidates[i]);
+            let same_as = |j: usize| this.is_some() && key(&candidates[j]) == this;
+            (i > 0 && same_as(i - 1)) || (i + 1 < candidates.len() && same_as(i + 1))
+        })
+        .collect()
+}
+
+/// The candidate lines as printed, under a header per group with
+/// `--group-alpha` or `--group-bearing` and in one unnamed group otherwise.
+pub(crate) fn candidate_groups(
+    candidates: &[Candidate],
+    tied: Vec<bool>,
+    guessed_country: &CountryData,
+    countries: &[CountryData],
+    config: &Config,
+    farthest_km: f64,
+) -> Vec<(Option<String>, Vec<String>)> {
+    let mut lines: Vec<(&Candidate, bool)> = candidates.iter().zip(tied).collect();
+    let sector_of = |candidate: &Candidate| {
+        countries.iter()
+            .find(|c| c.name == candidate.name)
+            .and_then(|target| bearing_degrees(guessed_country, target))
+            .map(bearing_sector)
+    };
+    // Stable sorts, so each group's candidates keep the chosen order.
+    if config.group_alpha {
+        lines.sort_by_key(|(candidate, _)| initial(&candidate.name));
+    } else if config.group_bearing {
+        // Clockwise from north, with any that have no bearing last.
+        lines.sort_by_key(|(candidate, _)| sector_of(candidate).unwrap_or(COMPASS_SECTORS.len()));
+    }
+
+    let mut groups: Vec<(Option<String>, Vec<String>)> = Vec::new();
+    for (candidate, tied) in lines {
+        let group = if config.group_alpha {
+            Some(initial(&candidate.name).to_string())
+        } else if config.group_bearing {
+            Some(sector_of(candidate).map_or("No bearing", |sector| COMPASS_SECTORS[sector]).to_string())
+        } else {
+            None
+        };
+        if groups.last().is_none_or(|(current, _)| *current != group) {
+            groups.push((group, Vec::new()));
+        }
+        let mut line = format_candidate(candidate, config, farthest_km);
+        let target = countries.iter().find(|c| c.name == candidate.name);
+        if config.polygons {
+            if let Some(target) = target {
+                line.push_str(&format!("  {}", describe_polygon_count(target)));
+            }
+        }
+        if config.population_file.is_some() {
+            if let Some(population) = target.and_then(|target| target.metadata.population) {
+                line.push_str(&format!("  pop. {}", describe_population(population)));
+            }
+        }
+        if config.bearing || config.bearing_degrees {
+            if let Some(degrees) = target.and_then(|target| bearing_degrees(guessed_country, target)) {
+                line.push_str(&format!("  {}", describe_bearing(degrees, config.bearing_degrees)));
+            }
+        }
+        if tied {
+            line.push_str(" (tied)");
+        }
+        if let Some((_, group_lines)) = groups.last_mut() {
+            group_lines.push(line);
+        }
+    }
+    groups
+}
+
+/// Upper-case first letter of a name, used as its group header.
+fn initial(name: &str) -> char {
+    name.chars()
+        .next()
+        .and_then(|c| c.to_uppercase().next())
+        .unwrap_or('?')
+}
+
+/// Width of the terminal stdout is attached to, or `None` when output is piped
+/// or redirected.
+pub(crate) fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| usize::from(width))
+}
+
+/// Lays `lines` out down as many columns as fit in `width`, or one per line
+/// when the width is unknown so piped output stays easy to process.
+pub(crate) fn in_columns(lines: &[String], width: Option<usize>) -> Vec<String> {
+    const GUTTER: usize = 2;
+    let cell = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) + GUTTER;
+    let columns = width.map_or(1, |width| (width / cell).max(1));
+    let rows = lines.len().div_ceil(columns);
+    (0..rows).map(|row| {
+        let cells: Vec<&String> = lines.iter().skip(row).step_by(rows).collect();
+        let mut text = String::new();
+        for (i, line) in cells.iter().enumerate() {
+            text.push_str(line);
+            if i + 1 < cells.len() {
+                text.push_str(&" ".repeat(cell - line.chars().count()));
+            }
+        }
+        text
+    }).collect()
+}
+
+fn format_candidate(candidate: &Candidate, config: &Config, farthest_km: f64) -> String {
+    let mut line = if config.names_only {
+        format!("- {}", candidate.name)
+    } else {
+        format!("- {} — {:.*} km", candidate.name, config.precision, candidate.distance_km)
+    };
+    if config.relative {
+        line.push_str(&format!("  {}", relative_bar(candidate.distance_km, farthest_km)));
+    }
+    line
+}
+
+/// A population rounded to three significant figures, e.g. `67.4M` or `812k`.
+fn describe_population(population: f64) -> String {
+    let (value, suffix) = if population >= 1e9 {
+        (population / 1e9, "B")
+    } else if population >= 1e6 {
+        (population / 1e6, "M")
+    } else if population >= 1e3 {
+        (population / 1e3, "k")
+    } else {
+        (population, "")
+    };
+    let decimals = if value >= 100.0 { 0 } else if value >= 10.0 { 1 } else { 2 };
+    format!("{:.*}{}", decimals, value, suffix)
+}
+
+/// How many separate polygons (mainland, islands, exclaves) make up a country.
+fn describe_polygon_count(country: &CountryData) -> String {
+    match country.geometry.0.len() {
+        1 => "1 polygon".to_string(),
+        count => format!("{} polygons", count),
+    }
+}
+
+/// Initial great-circle bearing from one country's centroid to another's, in
+/// degrees clockwise from north (0 to 360).
+fn bearing_degrees(from: &CountryData, to: &CountryData) -> Option<f64> {
+    let (from, to) = (from.geometry.centroid()?, to.geometry.centroid()?);
+    (from != to).then(|| from.haversine_bearing(to).rem_euclid(360.0))
+}
+
+const COMPASS_SECTORS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+/// Index into `COMPASS_SECTORS` of the sector a bearing falls in.
+fn bearing_sector(degrees: f64) -> usize {
+    ((degrees / 45.0).round() as usize) % COMPASS_SECTORS.len()
+}
+
+/// The 8-point compass sector for a bearing, optionally with the exact angle.
+fn describe_bearing(degrees: f64, exact: bool) -> String {
+    let sector = COMPASS_SECTORS[bearing_sector(degrees)];
+    if exact {
+        format!("{} {:.1}°", sector, degrees)
+    } else {
+        sector.to_string()
+    }
+}
+
+/// A ten-cell bar and percentage of `distance_km` relative to `farthest_km`.
+fn relative_bar(distance_km: f64, farthest_km: f64) -> String {
+    const WIDTH: usize = 10;
+    let fraction = if farthest_km > 0.0 { (distance_km / farthest_km).clamp(0.0, 1.0) } else { 1.0 };
+    let filled = (fraction * WIDTH as f64).round() as usize;
+    format!("[{}{}] {:>3.0}%", "#".repeat(filled), ".".repeat(WIDTH - filled), fraction * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{names, square, Solver};
+
+    #[test]
+    fn candidate_lines_show_the_distance_unless_names_only() {
+        let candidate = Candidate { name: "Beta".to_string(), distance_km: 111.236 };
+        let config = Config { precision: 2, ..Config::default() };
+        assert_eq!(format_candidate(&candidate, &config, 111.236), "- Beta — 111.24 km");
+        let names_only = Config { names_only: true, ..config };
+        assert_eq!(format_candidate(&candidate, &names_only, 111.236), "- Beta");
+    }
+
+    #[test]
+    fn the_farthest_candidate_shows_as_a_full_bar() {
+        let solver = Solver::fixture().with_config(|config| config.relative = true);
+        let candidates = solver.search("Alpha", "10000--10000");
+        let farthest_km = candidates.iter().map(|c| c.distance_km).fold(0.0, f64::max);
+        let lines: Vec<String> = candidates.iter()
+            .map(|candidate| format_candidate(candidate, &solver.config, farthest_km))
+            .collect();
+        let full: Vec<&String> = lines.iter().filter(|line| line.ends_with("[##########] 100%")).collect();
+        assert_eq!(full.len(), 1, "{:?}", lines);
+        assert!(full[0].starts_with("- Antipodia"));
+        // Beta, 111 km against nearly 20000 km, rounds to an empty bar.
+        assert_eq!(relative_bar(111.2, 19814.7), "[..........]   1%");
+        assert_eq!(relative_bar(1000.6, 2001.2), "[#####.....]  50%");
+    }
+
+    #[test]
+    fn equal_deviations_are_marked_as_tied() {
+        let candidate = |name: &str, distance_km| Candidate { name: name.to_string(), distance_km };
+        let mut candidates = vec![candidate("Zed", 530.0), candidate("Over", 510.0), candidate("Under", 490.0)];
+        let solver = Solver::fixture();
+        let query = solver.query("500--50");
+        let tied = order_candidates(&mut candidates, SortOrder::Deviation, Tiebreak::Alpha, &query, 0, &solver.countries);
+        assert_eq!(names(&candidates), ["Over", "Under", "Zed"]);
+        assert_eq!(tied, [true, true, false]);
+
+        // Equal at the shown precision counts as tied; hidden decimals don't rank them.
+        let mut candidates = vec![candidate("B", 510.04), candidate("A", 489.97)];
+        assert_eq!(order_candidates(&mut candidates, SortOrder::Deviation, Tiebreak::Alpha, &query, 1, &solver.countries), [true, true]);
+        let tied = order_candidates(&mut candidates, SortOrder::Deviation, Tiebreak::Alpha, &query, 2, &solver.countries);
+        assert_eq!((names(&candidates), tied), (vec!["A".to_string(), "B".to_string()], vec![false, false]));
+    }
+
+    #[test]
+    fn group_alpha_puts_candidates_under_their_initials() {
+        let solver = Solver::new(vec![
+            square("Home", 0.0, 0.0, 1.0),
+            square("Austria", 2.0, 0.0, 1.0),
+            square("Belgium", 4.0, 0.0, 1.0),
+            square("albania", 6.0, 0.0, 1.0),
+            square("Chile", 8.0, 0.0, 1.0),
+        ]).with_config(|config| { config.group_alpha = true; config.names_only = true; config.sort = SortOrder::Distance; });
+        let candidates = solver.search("Home", "10000--10000");
+        let tied = vec![false; candidates.len()];
+        let groups = candidate_groups(&candidates, tied, solver.country("Home"), &solver.countries, &solver.config, 0.0);
+        let expected = [("A", vec!["- Austria", "- albania"]), ("B", vec!["- Belgium"]), ("C", vec!["- Chile"])];
+        let expected: Vec<(Option<String>, Vec<String>)> = expected.iter()
+            .map(|(header, lines)| (Some(header.to_string()), lines.iter().map(|line| line.to_string()).collect()))
+            .collect();
+        assert_eq!(groups, expected);
+    }
+
+    #[test]
+    fn group_bearing_puts_candidates_under_their_compass_sectors() {
+        let solver = Solver::new(vec![
+            square("Home", -0.5, -0.5, 1.0),
+            square("Near North", -0.5, 5.0, 1.0),
+            square("Far North", -0.5, 20.0, 1.0),
+            square("Westerland", -12.0, -0.5, 1.0),
+            square("Northeast", 7.0, 7.0, 1.0),
+            square("Southeast", 9.0, -10.0, 1.0),
+        ]).with_config(|config| { config.group_bearing = true; config.names_only = true; });
+        let candidates = solver.search("Home", "10000--10000");
+        let tied = vec![false; candidates.len()];
+        let groups = candidate_groups(&candidates, tied, solver.country("Home"), &solver.countries, &solver.config, 0.0);
+        // Sectors go clockwise from north, each keeping the candidates' order.
+        let expected = [
+            ("N", vec!["- Far North", "- Near North"]),
+            ("NE", vec!["- Northeast"]),
+            ("SE", vec!["- Southeast"]),
+            ("W", vec!["- Westerland"]),
+        ];
+        let expected: Vec<(Option<String>, Vec<String>)> = expected.iter()
+            .map(|(header, lines)| (Some(header.to_string()), lines.iter().map(|line| line.to_string()).collect()))
+            .collect();
+        assert_eq!(groups, expected);
+    }
+
+    #[test]
+    fn bearing_degrees_point_the_right_way() {
+        let solver = Solver::new(vec![
+            square("Home", -0.5, -0.5, 1.0),
+            square("East", 9.5, -0.5, 1.0),
+            square("North", -0.5, 9.5, 1.0),
+            square("West", -10.5, -0.5, 1.0),
+        ]);
+        let bearing = |to: &str| bearing_degrees(solver.country("Home"), solver.country(to)).unwrap();
+        assert!((bearing("East") - 90.0).abs() < 0.01, "{}", bearing("East"));
+        assert!(bearing("North") < 0.01 || bearing("North") > 359.99, "{}", bearing("North"));
+        assert!((bearing("West") - 270.0).abs() < 0.01, "{}", bearing("West"));
+        assert_eq!(describe_bearing(bearing("East"), true), "E 90.0°");
+        assert_eq!(describe_bearing(bearing("East"), false), "E");
+        assert_eq!(describe_bearing(337.4, true), "NW 337.4°");
+        // A country has no bearing to itself.
+        assert!(bearing_degrees(solver.country("Home"), solver.country("Home")).is_none());
+    }
+
+    #[test]
+    fn the_tiebreak_orders_equal_distance_candidates() {
+        let mut countries = vec![square("Aruba", 0.0, 0.0, 0.2), square("Brazil", 10.0, 0.0, 5.0), square("Chad", 20.0, 0.0, 2.0)];
+        countries[0].metadata.population = Some(1_000.0);
+        countries[1].metadata.population = Some(100_000.0);
+        let solver = Solver::new(countries);
+        let query = solver.query("500--50");
+        let ordered = |tiebreak: Tiebreak| {
+            let mut candidates: Vec<Candidate> = ["Aruba", "Brazil", "Chad", "Denmark"].iter()
+                .zip([500.0, 500.0, 500.0, 499.0])
+                .map(|(name, distance_km)| Candidate { name: name.to_string(), distance_km })
+                .collect();
+            order_candidates(&mut candidates, SortOrder::Distance, tiebreak, &query, 1, &solver.countries);
+            names(&candidates)
+        };
+        // Distance still comes first; only the three at 500 km are reordered.
+        assert_eq!(ordered(Tiebreak::Alpha), ["Denmark", "Aruba", "Brazil", "Chad"]);
+        assert_eq!(ordered(Tiebreak::Area), ["Denmark", "Brazil", "Chad", "Aruba"]);
+        // Chad has no population estimate, so it goes last.
+        assert_eq!(ordered(Tiebreak::Population), ["Denmark", "Brazil", "Aruba", "Chad"]);
+        assert!("size".parse::<Tiebreak>().is_err());
+    }
+
+    #[test]
+    fn candidates_show_how_many_polygons_make_them_up() {
+        let solver = Solver::fixture().with_config(|config| config.polygons = true);
+        assert_eq!(describe_polygon_count(solver.country("Delta Islands")), "2 polygons");
+        assert_eq!(describe_polygon_count(solver.country("Beta")), "1 polygon");
+
+        let candidates = solver.search("Alpha", "1000--900");
+        let tied = vec![false; candidates.len()];
+        let groups = candidate_groups(&candidates, tied, solver.country("Alpha"), &solver.countries, &solver.config, 0.0);
+        assert_eq!(groups[0].1, ["- Beta — 111.2 km  1 polygon", "- Delta Islands — 1000.6 km  2 polygons"]);
+    }
+
+    #[test]
+    fn an_unknown_width_lays_candidates_out_one_per_line() {
+        let lines = ["- Alpha", "- Beta", "- Gamma", "- Delta Islands"].map(String::from).to_vec();
+        assert_eq!(in_columns(&lines, None), lines);
+        // Too narrow for two cells of the longest line plus the gutter.
+        assert_eq!(in_columns(&lines, Some(33)), lines);
+        assert_eq!(in_columns(&lines, Some(34)), [
+            "- Alpha          - Gamma",
+            "- Beta           - Delta Islands",
+        ]);
+        assert!(in_columns(&[], None).is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+//! Parsing and describing the distance a guess was answered with.
+
+use anyhow::{Result, Context};
+use crate::Config;
+
+/// A distance entered by the user together with how far below and above it the
+/// true distance may lie.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct DistanceInput {
+    pub(crate) distance_km: f64,
+    pub(crate) lower_margin_km: f64,
+    pub(crate) upper_margin_km: f64,
+    /// Whether the upper bound itself is excluded, as for a rounding interval.
+    pub(crate) upper_exclusive: bool,
+    /// The margin is widened until at least this many candidates match.
+    pub(crate) min_candidates: usize,
+}
+
+impl DistanceInput {
+    /// The band of true distances that the game would display as `distance_km`
+    /// when it rounds to the nearest `granularity_km`: `[d - g/2, d + g/2)`.
+    fn rounding_interval(distance_km: f64, granularity_km: f64) -> Self {
+        Self {
+            distance_km,
+            lower_margin_km: granularity_km / 2.0,
+            upper_margin_km: granularity_km / 2.0,
+            upper_exclusive: true,
+            min_candidates: 1,
+        }
+    }
+
+    fn has_margin(&self) -> bool {
+        self.lower_margin_km > 0.0 || self.upper_margin_km > 0.0
+    }
+
+    pub(crate) fn contains(&self, distance_km: f64) -> bool {
+        let below_upper = if self.upper_exclusive {
+            distance_km < self.upper_bound()
+        } else {
+            distance_km <= self.upper_bound()
+        };
+        distance_km >= self.lower_bound() && below_upper
+    }
+
+    pub(crate) fn lower_bound(&self) -> f64 {
+        self.distance_km - self.lower_margin_km
+    }
+
+    pub(crate) fn upper_bound(&self) -> f64 {
+        self.distance_km + self.upper_margin_km
+    }
+
+    /// Midway between the bounds, which differs from the entered distance
+    /// when the margins are asymmetric.
+    pub(crate) fn centre_km(&self) -> f64 {
+        (self.lower_bound() + self.upper_bound()) / 2.0
+    }
+
+    pub(crate) fn widest_margin_km(&self) -> f64 {
+        self.lower_margin_km.max(self.upper_margin_km)
+    }
+
+    pub(crate) fn widened(&self, step_km: f64) -> Self {
+        Self {
+            lower_margin_km: self.lower_margin_km + step_km,
+            upper_margin_km: self.upper_margin_km + step_km,
+            ..*self
+        }
+    }
+
+    /// The margin to `precision` decimals, without trailing zeros, so a
+    /// margin widened by fractional steps reads `26.5 km`, not
+    /// `26.500000000000004 km`.
+    pub(crate) fn describe_margin(&self, precision: usize) -> String {
+        let (lower, upper) = (format_km(self.lower_margin_km, precision), format_km(self.upper_margin_km, precision));
+        if lower == upper {
+            format!("{} km", lower)
+        } else {
+            format!("-{} / +{} km", lower, upper)
+        }
+    }
+}
+
+/// `km` to at most `precision` decimals, dropping trailing zeros.
+pub(crate) fn format_km(km: f64, precision: usize) -> String {
+    let text = format!("{:.*}", precision, km);
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
+/// A note for queries close to the farthest distance possible. Countries near
+/// a guess's antipode all sit around that distance, so the band tends to
+/// catch many of them and small errors move them in or out.
+pub(crate) fn near_max_warning(query: &DistanceInput, config: &Config) -> Option<String> {
+    let max_km = config.model.max_distance_km()?;
+    let gap_km = max_km - query.upper_bound();
+    (config.near_max_km > 0.0 && gap_km < config.near_max_km).then(|| {
+        format!(
+            "Note: {} km is within {:.0} km of the {:.0} km maximum, where distances bunch up; expect many candidates and check them with a second guess.",
+            query.distance_km,
+            config.near_max_km,
+            max_km,
+        )
+    })
+}
+
+/// Parses a typed distance with the configured delimiter, reading a bare
+/// distance as the game's rounding interval in `--game-uncertainty` mode and
+/// widening it by the data's uncertainty with `--data-uncertainty`.
+pub(crate) fn parse_query(input: &str, config: &Config) -> Result<DistanceInput> {
+    let mut query = parse_distance_input(input, &config.delimiter)?;
+    if config.game_uncertainty && !query.has_margin() {
+        query = DistanceInput {
+            min_candidates: query.min_candidates,
+            ..DistanceInput::rounding_interval(query.distance_km, config.rounding_km)
+        };
+    }
+    if let Some(uncertainty_km) = config.data_uncertainty_km {
+        query = query.widened(uncertainty_km);
+    }
+    Ok(query)
+}
+
+/// Parses `distance`, `distance--margin` or the asymmetric `distance--lower+upper`,
+/// where `--` is the configured `delimiter`. `distance--n5` instead starts with
+/// no margin and widens it until at least 5 candidates match.
+fn parse_distance_input(input: &str, delimiter: &str) -> Result<DistanceInput> {
+    let input = input.trim();
+    let parts: Vec<&str> = input.split(delimiter).collect();
+
+    match parts.len() {
+        1 => {
+            let distance = parse_km(parts[0], "Invalid distance format")?;
+            if distance < 0.0 {
+                anyhow::bail!("Distance cannot be negative");
+            }
+            Ok(DistanceInput {
+                distance_km: distance,
+                lower_margin_km: 0.0,
+                upper_margin_km: 0.0,
+                upper_exclusive: false,
+                min_candidates: 1,
+            })
+        }
+        2 => {
+            let distance = parse_km(parts[0], "Invalid distance format")?;
+            if let Some(count) = parts[1].strip_prefix(['n', 'N']) {
+                let min_candidates: usize = count.parse().context("Invalid candidate count")?;
+                if distance < 0.0 || min_candidates == 0 {
+                    anyhow::bail!("Distance must be non-negative and the candidate count positive");
+                }
+                return Ok(DistanceInput {
+                    distance_km: distance,
+                    lower_margin_km: 0.0,
+                    upper_margin_km: 0.0,
+                    upper_exclusive: false,
+                    min_candidates,
+                });
+            }
+            let (lower, upper) = match parts[1].split_once('+') {
+                Some((lower, upper)) => (
+                    parse_km(lower, "Invalid lower margin format")?,
+                    parse_km(upper, "Invalid upper margin format")?,
+                ),
+                None => {
+                    let margin = parse_km(parts[1], "Invalid margin format")?;
+                    (margin, margin)
+                }
+            };
+            if distance < 0.0 || lower < 0.0 || upper < 0.0 {
+                anyhow::bail!("Distance and margin must be non-negative");
+            }
+            Ok(DistanceInput {
+                distance_km: distance,
+                lower_margin_km: lower,
+                upper_margin_km: upper,
+                upper_exclusive: false,
+                min_candidates: 1,
+            })
+        }
+        _ => anyhow::bail!(
+            "Invalid input format. Use 'distance', 'distance{d}margin', 'distance{d}lower+upper' or 'distance{d}n<count>'",
+            d = delimiter,
+        )
+    }
+}
+
+/// A distance or margin in km. `f64` also parses `inf`, `NaN` and overflowing
+/// values like `1e400`, none of which is a distance.
+fn parse_km(text: &str, context: &'static str) -> Result<f64> {
+    let km: f64 = text.parse().context(context)?;
+    if !km.is_finite() {
+        anyhow::bail!("Invalid distance format: '{}' is not a finite number", text);
+    }
+    Ok(km)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Solver, names, square};
+
+    #[test]
+    fn asymmetric_margin_sets_each_bound() {
+        let query = parse_distance_input("500--0+50", "--").unwrap();
+        assert_eq!((query.lower_bound(), query.upper_bound()), (500.0, 550.0));
+        assert!(query.contains(500.0) && query.contains(550.0));
+        assert!(!query.contains(499.9) && !query.contains(550.1));
+    }
+
+    #[test]
+    fn game_uncertainty_uses_the_rounding_interval() {
+        let config = Config { game_uncertainty: true, ..Config::default() };
+        let query = parse_query("500", &config).unwrap();
+        assert_eq!((query.lower_bound(), query.upper_bound()), (499.5, 500.5));
+        assert!(query.contains(499.5) && query.contains(500.49));
+        assert!(!query.contains(500.5));
+
+        let coarse = Config { rounding_km: 10.0, ..config.clone() };
+        let query = parse_query("500", &coarse).unwrap();
+        assert_eq!((query.lower_bound(), query.upper_bound()), (495.0, 505.0));
+        // A typed margin wins over the rounding interval.
+        assert_eq!(parse_query("500--50", &config).unwrap().upper_bound(), 550.0);
+    }
+
+    #[test]
+    fn a_custom_delimiter_separates_distance_and_margin() {
+        for delimiter in [":", "/"] {
+            let config = Config { delimiter: delimiter.to_string(), ..Config::default() };
+            let query = parse_query(&format!("800{}50", delimiter), &config).unwrap();
+            assert_eq!((query.distance_km, query.lower_margin_km, query.upper_margin_km), (800.0, 50.0, 50.0));
+            let query = parse_query(&format!("800{}10+40", delimiter), &config).unwrap();
+            assert_eq!((query.lower_bound(), query.upper_bound()), (790.0, 840.0));
+            assert_eq!(parse_query(&format!("800{}n3", delimiter), &config).unwrap().min_candidates, 3);
+            // `--` is just unparsable text once another delimiter is chosen.
+            assert!(parse_query("800--50", &config).is_err());
+        }
+    }
+
+    #[test]
+    fn a_near_max_query_warns_and_matches_the_antipodal_cluster() {
+        let solver = Solver::new(vec![
+            square("Home", 0.0, 0.0, 1.0),
+            square("Far East", -178.0, -0.5, 0.5),
+            square("Farther East", -176.0, -0.5, 0.5),
+            square("Farthest East", -174.0, -0.5, 0.5),
+            square("Elsewhere", 100.0, 0.0, 1.0),
+        ]);
+        let query = solver.query("19500--500");
+        let warning = near_max_warning(&query, &solver.config).unwrap();
+        assert!(warning.contains("within 500 km of the 20015 km maximum"), "{}", warning);
+        assert_eq!(names(&solver.search("Home", "19500--500")), ["Far East", "Farther East", "Farthest East"]);
+
+        assert!(near_max_warning(&solver.query("10000--500"), &solver.config).is_none());
+        let silenced = Config { near_max_km: 0.0, ..solver.config.clone() };
+        assert!(near_max_warning(&query, &silenced).is_none());
+    }
+
+    #[test]
+    fn non_finite_distances_and_margins_are_rejected() {
+        for input in ["1e400", "inf", "NaN", "inf--5", "500--inf", "500--5+NaN", "500--5+1e400", "-inf--n3"] {
+            let error = parse_distance_input(input, "--").unwrap_err().to_string();
+            assert!(error.starts_with("Invalid distance format: '"), "{}: {}", input, error);
+        }
+        assert_eq!(parse_distance_input("1e3--5", "--").unwrap().distance_km, 1000.0);
+    }
+}
@@ -0,0 +1,175 @@
+//! The `--serve` HTTP endpoint, which streams matches as they are found.
+
+use std::io::{BufRead, BufReader, Write};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use anyhow::{Result, Context};
+use crate::{Config, CountryData, SearchProgress, fallback_note, find_country, mystery_candidates};
+use crate::cache::DistanceCache;
+use crate::query::parse_query;
+use crate::session::Session;
+
+/// Serves `GET /solve-stream?guess=<country>&distance=<distance>` as
+/// server-sent events: a `candidate` event for each match as soon as it is
+/// found, then `done` with the count. Requests are handled one at a time.
+pub(crate) fn run_server(address: &str, countries: &[CountryData], cache: &Mutex<DistanceCache>, config: &Config) -> Result<()> {
+    let listener = std::net::TcpListener::bind(address).with_context(|| format!("Failed to listen on {}", address))?;
+    println!("Listening on http://{}/solve-stream", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let result = stream
+            .context("Failed to accept connection")
+            .and_then(|stream| handle_request(stream, countries, cache, config));
+        if let Err(e) = result {
+            println!("Request failed: {:#}", e);
+        }
+        if let Some(note) = fallback_note(cache, config) {
+            println!("{}", note);
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(
+    mut stream: std::net::TcpStream,
+    countries: &[CountryData],
+    cache: &Mutex<DistanceCache>,
+    config: &Config,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers carry nothing this endpoint needs.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+    if method != "GET" || path != "/solve-stream" {
+        return write_plain_response(&mut stream, "404 Not Found", "Not found");
+    }
+
+    let params: HashMap<String, String> = query_string.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+        .collect();
+    let (Some(guess), Some(distance)) = (params.get("guess"), params.get("distance")) else {
+        return write_plain_response(&mut stream, "400 Bad Request", "Use /solve-stream?guess=<country>&distance=<distance>");
+    };
+    let Some(guess) = find_country(countries, guess) else {
+        return write_plain_response(&mut stream, "404 Not Found", &format!("Country '{}' not found", guess));
+    };
+    let query = match parse_query(distance, config) {
+        Ok(query) => query,
+        Err(e) => return write_plain_response(&mut stream, "400 Bad Request", &format!("Invalid distance: {}", e)),
+    };
+
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    )?;
+    let session = Session {
+        landlocked: config.landlocked,
+        min_population: config.min_population,
+        ..Session::default()
+    };
+    let progress = SearchProgress::default();
+    let mut count = 0;
+    for candidate in mystery_candidates(guess, &query, countries, cache, config, &session, &progress) {
+        count += 1;
+        let data = serde_json::json!({ "name": candidate.name, "distance_km": candidate.distance_km });
+        write_event(&mut stream, "candidate", &data)?;
+    }
+    write_event(&mut stream, "done", &serde_json::json!({ "count": count }))
+}
+
+fn write_event(stream: &mut impl Write, event: &str, data: &serde_json::Value) -> Result<()> {
+    write!(stream, "event: {}\ndata: {}\n\n", event, data)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn write_plain_response(stream: &mut impl Write, status: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    )?;
+    Ok(())
+}
+
+/// Decodes a URL query component: `+` is a space and `%XX` a byte.
+pub(crate) fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = input.by_ref().take(2).collect();
+                match std::str::from_utf8(&hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(decoded) => bytes.push(decoded),
+                    None => {
+                        bytes.push(b'%');
+                        bytes.extend(hex);
+                    }
+                }
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::test_support::Solver;
+
+    /// Sends `request` to `handle_request` over a real socket and returns the response.
+    fn respond(solver: &Solver, request: &str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::scope(|scope| {
+            let server = scope.spawn(|| {
+                let (stream, _) = listener.accept().unwrap();
+                handle_request(stream, &solver.countries, &solver.cache, &solver.config).unwrap();
+            });
+            let mut client = std::net::TcpStream::connect(address).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            server.join().unwrap();
+            response
+        })
+    }
+
+    #[test]
+    fn the_stream_sends_each_candidate_then_done() {
+        let solver = Solver::fixture();
+        let response = respond(&solver, "GET /solve-stream?guess=alpha&distance=1000%2D%2D50 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n") && head.contains("Content-Type: text/event-stream"), "{}", head);
+        let delta_km = solver.distance_km("Alpha", "Delta Islands");
+        assert_eq!(body, format!(
+            "event: candidate\ndata: {{\"distance_km\":{},\"name\":\"Delta Islands\"}}\n\nevent: done\ndata: {{\"count\":1}}\n\n",
+            delta_km,
+        ));
+
+        let response = respond(&solver, "GET /solve-stream?guess=Alpha&distance=10000--10000 HTTP/1.1\r\n\r\n");
+        let events: Vec<&str> = response.lines().filter_map(|line| line.strip_prefix("event: ")).collect();
+        assert_eq!(events, ["candidate", "candidate", "candidate", "candidate", "done"]);
+    }
+
+    #[test]
+    fn bad_requests_get_plain_errors() {
+        let solver = Solver::fixture();
+        assert!(respond(&solver, "GET /solve HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found"));
+        assert!(respond(&solver, "GET /solve-stream?guess=Alpha HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(respond(&solver, "GET /solve-stream?guess=Narnia&distance=5 HTTP/1.1\r\n\r\n").ends_with("Country 'Narnia' not found"));
+    }
+}
@@ -0,0 +1,462 @@
+//! A game in progress: its guesses, candidate pool, share tokens and replays.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::{Result, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use crate::{Candidate, Config, CountryData, SearchProgress, find_country, search_with_expansion};
+use crate::cache::DistanceCache;
+use untitled3::distance::DistanceModel;
+use crate::query::{DistanceInput, parse_query};
+use crate::server::percent_decode;
+
+/// One guess of the current game and the band of distances it allowed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Constraint {
+    pub(crate) guess: String,
+    pub(crate) query: DistanceInput,
+}
+
+impl Constraint {
+    pub(crate) fn describe(&self, precision: usize) -> String {
+        format!("{} at {} km (margin {})", self.guess, self.query.distance_km, self.query.describe_margin(precision))
+    }
+}
+
+/// State that carries over between queries in one run.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    /// When set, only these countries are considered as candidates.
+    pub(crate) pool: Option<Vec<String>>,
+    /// Guesses made so far in the current game.
+    pub(crate) constraints: Vec<Constraint>,
+    /// Countries consistent with every constraint, once there is one.
+    pub(crate) remaining: Option<Vec<String>>,
+    /// Countries already guessed, which can't be the answer.
+    pub(crate) guessed: Vec<String>,
+    /// When set, only landlocked (`true`) or coastal (`false`) countries are
+    /// candidates. Countries the data doesn't describe are kept.
+    pub(crate) landlocked: Option<bool>,
+    /// When set, only countries with at least this population are candidates.
+    /// Countries without a population are kept.
+    pub(crate) min_population: Option<f64>,
+    /// Results of queries already answered, valid while the pool and guessed
+    /// countries stay the same.
+    pub(crate) results: HashMap<ResultKey, (DistanceInput, Vec<Candidate>)>,
+}
+
+/// A guess and the exact distance input typed for it.
+type ResultKey = (String, [u64; 3], bool, usize);
+
+fn result_key(guess: &str, query: &DistanceInput) -> ResultKey {
+    let bounds = [query.distance_km, query.lower_margin_km, query.upper_margin_km].map(f64::to_bits);
+    (guess.to_string(), bounds, query.upper_exclusive, query.min_candidates)
+}
+
+impl Session {
+    pub(crate) fn allows(&self, country: &CountryData) -> bool {
+        self.pool.as_ref().is_none_or(|pool| pool.contains(&country.name))
+            && !self.guessed.contains(&country.name)
+            && self.landlocked.is_none_or(|wanted| {
+                country.metadata.landlocked.is_none_or(|landlocked| landlocked == wanted)
+            })
+            && self.min_population.is_none_or(|minimum| {
+                country.metadata.population.is_none_or(|population| population >= minimum)
+            })
+    }
+
+    fn cached_result(&self, guess: &str, query: &DistanceInput) -> Option<&(DistanceInput, Vec<Candidate>)> {
+        self.results.get(&result_key(guess, query))
+    }
+
+    fn store_result(&mut self, guess: &str, query: &DistanceInput, result: (DistanceInput, Vec<Candidate>)) {
+        self.results.insert(result_key(guess, query), result);
+    }
+
+    /// The result of an earlier identical query, or else of `search`, kept for
+    /// next time. A search returning `None`, as a cancelled one does, is not kept.
+    pub(crate) fn result_or_search<F>(&mut self, guess: &str, query: &DistanceInput, search: F) -> Option<(DistanceInput, Vec<Candidate>)>
+    where
+        F: FnOnce(&Self) -> Option<(DistanceInput, Vec<Candidate>)>,
+    {
+        if let Some(result) = self.cached_result(guess, query) {
+            return Some(result.clone());
+        }
+        let result = search(self)?;
+        self.store_result(guess, query, result.clone());
+        Some(result)
+    }
+
+    /// Forgets cached results after a change to which countries may be candidates.
+    pub(crate) fn invalidate_results(&mut self) {
+        self.results.clear();
+    }
+
+    pub(crate) fn mark_guessed(&mut self, name: &str) {
+        if !self.guessed.iter().any(|guessed| guessed == name) {
+            self.guessed.push(name.to_string());
+            self.invalidate_results();
+        }
+        if let Some(remaining) = &mut self.remaining {
+            remaining.retain(|candidate| candidate != name);
+        }
+    }
+
+    /// Adds a guess to the current game, narrowing the remaining candidates to
+    /// those that also matched this guess.
+    pub(crate) fn record(&mut self, guess: &str, query: DistanceInput, candidates: &[Candidate]) {
+        self.constraints.push(Constraint { guess: guess.to_string(), query });
+        let names = candidates.iter().map(|c| c.name.clone());
+        match &mut self.remaining {
+            Some(remaining) => remaining.retain(|name| candidates.iter().any(|c| &c.name == name)),
+            None => self.remaining = Some(names.collect()),
+        }
+    }
+
+    /// Starts a new game, keeping settings such as the pool.
+    pub(crate) fn reset_game(&mut self) {
+        self.constraints.clear();
+        self.remaining = None;
+    }
+
+    /// Packs the game so far into a single copy-pasteable token.
+    pub(crate) fn share_token(&self, model: DistanceModel) -> Result<String> {
+        let shared = SharedSession {
+            model,
+            pool: self.pool.clone(),
+            guessed: self.guessed.clone(),
+            constraints: self.constraints.clone(),
+            remaining: self.remaining.clone(),
+        };
+        Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(&shared)?))
+    }
+
+    /// Replaces the game with the one packed into a `share` token.
+    pub(crate) fn load_token(&mut self, token: &str) -> Result<DistanceModel> {
+        let bytes = URL_SAFE_NO_PAD.decode(token.trim()).context("Token is not valid base64")?;
+        let shared: SharedSession = serde_json::from_slice(&bytes).context("Token does not hold a saved game")?;
+        self.pool = shared.pool;
+        self.guessed = shared.guessed;
+        self.constraints = shared.constraints;
+        self.remaining = shared.remaining;
+        self.invalidate_results();
+        Ok(shared.model)
+    }
+}
+
+/// Parses a pasted game history into its guesses. The format:
+///
+/// - entries are `<country>:<distance>`, separated by `;` or newlines, with
+///   the distance in the same syntax as at the prompt (`800`, `800--50`...);
+/// - the history may be the value of a `guesses=` parameter in a URL, in which
+///   case only that parameter is read and it is percent-decoded.
+///
+/// Any malformed entry fails the whole history, so nothing is half-applied.
+fn parse_history<'a>(
+    text: &str,
+    countries: &'a [CountryData],
+    config: &Config,
+) -> Result<Vec<(&'a CountryData, DistanceInput)>> {
+    let text = match text.split_once("guesses=") {
+        Some((_, parameter)) => percent_decode(parameter.split('&').next().unwrap_or_default()),
+        None => text.to_string(),
+    };
+    let entries: Vec<&str> = text.split([';', '\n']).map(str::trim).filter(|entry| !entry.is_empty()).collect();
+    if entries.is_empty() {
+        anyhow::bail!("The history has no guesses; expected entries like 'France:1200; Spain:800'");
+    }
+
+    entries.iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let context = || format!("Entry {} ('{}')", i + 1, entry);
+            // Split at the first colon, so `--delimiter :` distances such
+            // as `France:1200:50` still work.
+            let (name, distance) = entry.split_once(':')
+                .with_context(|| format!("{}: expected <country>:<distance>", context()))?;
+            let country = find_country(countries, name)
+                .with_context(|| format!("{}: no country named '{}'", context(), name.trim()))?;
+            let query = parse_query(distance, config).with_context(context)?;
+            Ok((country, query))
+        })
+        .collect()
+}
+
+/// Handles `replay <history>`: starts a new game from a pasted history and
+/// shows the candidates left after all of its guesses. Each guess widens its
+/// margin when too little matches, as a typed guess would.
+pub(crate) fn replay_history(
+    history: &str,
+    session: &mut Session,
+    countries: &[CountryData],
+    cache: &Arc<Mutex<DistanceCache>>,
+    config: &Config,
+) {
+    let entries = match parse_history(history, countries, config) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("Error: {:#}", e);
+            return;
+        }
+    };
+
+    session.reset_game();
+    let progress = SearchProgress::default();
+    for (country, query) in &entries {
+        session.mark_guessed(&country.name);
+        let (query, candidates) = search_with_expansion(country, *query, countries, cache, config, session, &progress);
+        let constraint = Constraint { guess: country.name.clone(), query };
+        // As at the prompt, a guess that matches nothing narrows nothing.
+        if candidates.is_empty() {
+            println!("{}: no countries found even with increased margin, so it is left out", constraint.describe(config.precision));
+            continue;
+        }
+        println!("{}: {} candidate(s)", constraint.describe(config.precision), candidates.len());
+        session.record(&country.name, query, &candidates);
+    }
+    let remaining = session.remaining.as_deref().unwrap_or_default();
+    println!("Replayed {} guess(es); remaining ({}): {}", entries.len(), remaining.len(), remaining.join(", "));
+}
+
+/// What a `share` token carries: the game's guesses and the settings that
+/// decide its candidates.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SharedSession {
+    model: DistanceModel,
+    pool: Option<Vec<String>>,
+    guessed: Vec<String>,
+    constraints: Vec<Constraint>,
+    remaining: Option<Vec<String>>,
+}
+
+/// Handles `pool` (show), `pool clear` and `pool A, B, ...` (restrict candidates).
+pub(crate) fn update_pool(session: &mut Session, args: &str, countries: &[CountryData]) {
+    if args.is_empty() {
+        match &session.pool {
+            Some(pool) => println!("Candidate pool ({}): {}", pool.len(), pool.join(", ")),
+            None => println!("No candidate pool set; all countries are considered."),
+        }
+        return;
+    }
+
+    if args.eq_ignore_ascii_case("clear") {
+        session.pool = None;
+        session.invalidate_results();
+        println!("Candidate pool cleared.");
+        return;
+    }
+
+    // `pool from <file>` reads one country per line, as written by `export`.
+    let names: Vec<String> = match args.strip_prefix("from ") {
+        Some(path) => match std::fs::read_to_string(path.trim()) {
+            Ok(contents) => contents.lines().map(String::from).collect(),
+            Err(e) => {
+                println!("Error: Failed to read {}: {}", path.trim(), e);
+                return;
+            }
+        },
+        None => args.split(',').map(String::from).collect(),
+    };
+
+    let mut pool = Vec::new();
+    for name in names.iter().map(|name| name.trim()).filter(|name| !name.is_empty()) {
+        match find_country(countries, name) {
+            Some(country) if !pool.contains(&country.name) => pool.push(country.name.clone()),
+            Some(_) => {}
+            None => println!("Warning: Country '{}' not found in database, skipping", name),
+        }
+    }
+
+    if pool.is_empty() {
+        println!("Error: No known countries given; pool unchanged");
+    } else {
+        pool.sort();
+        println!("Candidate pool set to {} countries: {}", pool.len(), pool.join(", "));
+        session.pool = Some(pool);
+        session.invalidate_results();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use untitled3::distance::Ellipsoid;
+    use crate::test_support::{names, square, Solver};
+
+    #[test]
+    fn queries_only_return_pool_members() {
+        let mut solver = Solver::fixture();
+        update_pool(&mut solver.session, "beta, Antipodia, Nowhere", &solver.countries.clone());
+        assert_eq!(solver.session.pool.as_deref(), Some(&["Antipodia".to_string(), "Beta".to_string()][..]));
+        assert_eq!(names(&solver.search("Alpha", "10000--10000")), ["Antipodia", "Beta"]);
+
+        update_pool(&mut solver.session, "clear", &[]);
+        assert_eq!(solver.search("Alpha", "10000--10000").len(), 4);
+    }
+
+    #[test]
+    fn guessed_countries_never_reappear_as_candidates() {
+        let mut solver = Solver::fixture();
+        let query = solver.query("10000--10000");
+        let everything = solver.search("Alpha", "10000--10000");
+        solver.session.store_result("Alpha", &query, (query, everything));
+
+        solver.guess("Beta", "10000--10000");
+        // The earlier result listed Beta, so it mustn't be reused.
+        assert!(solver.session.cached_result("Alpha", &query).is_none());
+        assert!(!names(&solver.search("Alpha", "10000--10000")).contains(&"Beta".to_string()));
+        assert!(!solver.guess("Gamma", "10000--10000").contains(&"Beta".to_string()));
+        assert!(!solver.remaining().iter().any(|name| name == "Beta" || name == "Gamma"));
+
+        solver.session.guessed.clear();
+        assert!(names(&solver.search("Alpha", "10000--10000")).contains(&"Beta".to_string()));
+    }
+
+    #[test]
+    fn a_repeated_query_reuses_the_stored_result() {
+        let mut solver = Solver::fixture();
+        let query = solver.query("1000--50");
+        let found = solver.search("Alpha", "1000--50");
+        let mut searches = 0;
+        for _ in 0..2 {
+            let (_, candidates) = solver.session.result_or_search("Alpha", &query, |_| {
+                searches += 1;
+                Some((query, found.clone()))
+            }).unwrap();
+            assert_eq!(names(&candidates), ["Delta Islands"]);
+        }
+        assert_eq!(searches, 1);
+
+        // A margin that differs only in its last digit is a new query.
+        let wider = solver.query("1000--50.000001");
+        solver.session.result_or_search("Alpha", &wider, |_| { searches += 1; Some((wider, Vec::new())) });
+        assert_eq!(searches, 2);
+        // Changing who may be a candidate forgets every stored result; a
+        // cancelled search leaves nothing behind.
+        update_pool(&mut solver.session, "Beta, Gamma", &solver.countries.clone());
+        assert!(solver.session.result_or_search("Alpha", &query, |_| None).is_none());
+        assert!(solver.session.cached_result("Alpha", &query).is_none());
+    }
+
+    #[test]
+    fn a_share_token_restores_the_game() {
+        let mut solver = Solver::fixture();
+        update_pool(&mut solver.session, "Alpha, Beta, Gamma, Delta Islands", &solver.countries.clone());
+        solver.guess("Beta", "10000--10000");
+        solver.guess("Alpha", "1000--0+50");
+        let model = DistanceModel::Geodesic(Ellipsoid::WGS84);
+        let token = solver.session.share_token(model).unwrap();
+
+        let mut restored = Solver::fixture();
+        restored.guess("Antipodia", "19814.7--1");
+        assert_eq!(restored.session.load_token(&format!(" {}\n", token)).unwrap(), model);
+        let game = |session: &Session| {
+            let constraints: Vec<(String, DistanceInput)> = session.constraints.iter().map(|c| (c.guess.clone(), c.query)).collect();
+            (session.pool.clone(), session.guessed.clone(), constraints, session.remaining.clone())
+        };
+        assert_eq!(game(&restored.session), game(&solver.session));
+        assert_eq!(restored.remaining(), ["Delta Islands"]);
+        assert!(restored.session.results.is_empty());
+
+        assert!(restored.session.load_token("not a token!").is_err());
+        assert_eq!(game(&restored.session), game(&solver.session));
+    }
+
+    #[test]
+    fn the_landlocked_filter_drops_an_equally_distant_coastal_country() {
+        let landlocked = |mut country: CountryData, value: Option<bool>| {
+            country.metadata.landlocked = value;
+            country
+        };
+        let mut solver = Solver::new(vec![
+            square("Home", -0.5, -0.5, 1.0),
+            landlocked(square("Inland", 4.5, -0.5, 1.0), Some(true)),
+            landlocked(square("Seaside", -5.5, -0.5, 1.0), Some(false)),
+            landlocked(square("Uncharted", -0.5, 4.5, 1.0), None),
+        ]);
+        let distance = format!("{}--1", solver.distance_km("Home", "Inland"));
+        assert_eq!(names(&solver.search("Home", &distance)), ["Inland", "Seaside", "Uncharted"]);
+
+        solver.session.landlocked = Some(true);
+        // Countries the data says nothing about are kept.
+        assert_eq!(names(&solver.search("Home", &distance)), ["Inland", "Uncharted"]);
+        solver.session.landlocked = Some(false);
+        assert_eq!(names(&solver.search("Home", &distance)), ["Seaside", "Uncharted"]);
+    }
+
+    #[test]
+    fn the_minimum_population_drops_an_equally_distant_small_country() {
+        let populated = |mut country: CountryData, population: Option<f64>| {
+            country.metadata.population = population;
+            country
+        };
+        let mut solver = Solver::new(vec![
+            square("Home", -0.5, -0.5, 1.0),
+            populated(square("Big", 4.5, -0.5, 1.0), Some(5e6)),
+            populated(square("Small", -5.5, -0.5, 1.0), Some(2e4)),
+            populated(square("Uncounted", -0.5, 4.5, 1.0), None),
+        ]);
+        let distance = format!("{}--1", solver.distance_km("Home", "Big"));
+        assert_eq!(names(&solver.search("Home", &distance)), ["Big", "Small", "Uncounted"]);
+
+        solver.session.min_population = Some(1e6);
+        assert_eq!(names(&solver.search("Home", &distance)), ["Big", "Uncounted"]);
+        // The minimum itself is allowed.
+        solver.session.min_population = Some(2e4);
+        assert_eq!(names(&solver.search("Home", &distance)), ["Big", "Small", "Uncounted"]);
+    }
+
+    #[test]
+    fn a_pasted_history_parses_into_its_guesses() {
+        let solver = Solver::fixture();
+        let parse = |text: &str, config: &Config| {
+            parse_history(text, &solver.countries, config)
+                .map(|entries| entries.into_iter().map(|(country, query)| (country.name.as_str(), query)).collect::<Vec<_>>())
+                .map_err(|e| format!("{:#}", e))
+        };
+        let config = &solver.config;
+        let expected = Ok(vec![("Alpha", solver.query("1000--50")), ("Beta", solver.query("800"))]);
+        assert_eq!(parse("alpha:1000--50; Beta: 800\n", config), expected);
+        assert_eq!(parse("https://globle.example/?day=3&guesses=Alpha%3A1000--50%3BBeta%3A800&lang=en", config), expected);
+
+        let colon = Config { delimiter: ":".to_string(), ..config.clone() };
+        assert_eq!(parse("Alpha:1000:50;Beta:800", &colon), Ok(vec![("Alpha", parse_query("1000:50", &colon).unwrap()), ("Beta", parse_query("800", &colon).unwrap())]));
+
+        assert_eq!(parse("Alpha:1000; Beta 800", config), Err("Entry 2 ('Beta 800'): expected <country>:<distance>".to_string()));
+        assert_eq!(parse("Narnia:5", config), Err("Entry 1 ('Narnia:5'): no country named 'Narnia'".to_string()));
+        assert!(parse("Alpha:far", config).unwrap_err().starts_with("Entry 1 ('Alpha:far'): "));
+        assert!(parse(" ; \n", config).is_err());
+    }
+
+    #[test]
+    fn a_replayed_guess_widens_its_margin_like_a_typed_one() {
+        let mut solver = Solver::fixture();
+        solver.guess("Gamma", "0--5");
+        let mut session = std::mem::take(&mut solver.session);
+        // Delta Islands is 1000.6 km from Alpha, outside the typed band.
+        replay_history("Alpha:1000--0.1", &mut session, &solver.countries, &solver.cache, &solver.config);
+        solver.session = session;
+        assert_eq!(solver.remaining(), ["Delta Islands"]);
+        // The earlier game's constraints go; its guesses stay guessed.
+        assert_eq!(solver.session.guessed, ["Gamma", "Alpha"]);
+        assert_eq!(solver.session.constraints.len(), 1);
+        assert_eq!(solver.session.constraints[0].query.upper_margin_km, 1.1);
+    }
+
+    #[test]
+    fn a_replayed_guess_matching_nothing_leaves_the_candidates_alone() {
+        let mut solver = Solver::fixture();
+        let mut session = std::mem::take(&mut solver.session);
+        replay_history("Alpha:1000--0.1; Beta:5", &mut session, &solver.countries, &solver.cache, &solver.config);
+        solver.session = session;
+        assert_eq!(solver.remaining(), ["Delta Islands"]);
+        assert_eq!(solver.session.constraints.len(), 1);
+        assert_eq!(solver.session.guessed, ["Alpha", "Beta"]);
+
+        // Typed at the prompt, the same guesses end the same way.
+        let mut typed = Solver::fixture();
+        typed.guess("Alpha", "1000--0.1");
+        typed.guess("Beta", "5");
+        assert_eq!(typed.remaining(), solver.remaining());
+    }
+}
@@ -0,0 +1,123 @@
+//! Helpers for unit tests: a solver over the synthetic fixture or hand-made
+//! countries, so tests never need the real `country_data.json`.
+
+use std::sync::{Arc, Mutex};
+use geojson::{Feature, Geometry, JsonObject, Value};
+use crate::{cached_distance_km, find_country, find_mystery_countries, search_with_expansion};
+use crate::{Candidate, Config, CountryData, SearchProgress};
+use crate::cache::DistanceCache;
+use crate::data::{country_from_feature, stream_country_data, UnknownGeometryPolicy};
+use crate::query::{parse_query, DistanceInput};
+use crate::session::Session;
+
+/// `fixtures/synthetic_countries.json`, built into the test binary.
+pub(crate) const FIXTURE: &str = include_str!("../fixtures/synthetic_countries.json");
+
+/// The fixture's countries: squares Alpha, Beta and Gamma near 0,0, the
+/// two-part Delta Islands about 1000 km east and Antipodia near the antipode.
+pub(crate) fn fixture_countries() -> Vec<CountryData> {
+    stream_country_data(FIXTURE.as_bytes(), UnknownGeometryPolicy::Error)
+        .expect("the fixture is valid GeoJSON")
+        .expect("the fixture is a FeatureCollection")
+}
+
+/// A country made of the given rings of lon,lat pairs, one polygon per ring.
+pub(crate) fn country(name: &str, rings: &[&[(f64, f64)]]) -> CountryData {
+    let polygons: Vec<Vec<Vec<Vec<f64>>>> = rings.iter()
+        .map(|ring| vec![ring.iter().map(|&(lon, lat)| vec![lon, lat]).collect()])
+        .collect();
+    let mut properties = JsonObject::new();
+    properties.insert("NAME".to_string(), name.into());
+    let feature = Feature {
+        geometry: Some(Geometry::new(Value::MultiPolygon(polygons))),
+        properties: Some(properties),
+        ..Feature::default()
+    };
+    country_from_feature(&feature).expect("a polygon country")
+}
+
+/// A `size`-degree square country with its south-west corner at `lon`,`lat`.
+pub(crate) fn square(name: &str, lon: f64, lat: f64, size: f64) -> CountryData {
+    let ring = [(lon, lat), (lon + size, lat), (lon + size, lat + size), (lon, lat + size), (lon, lat)];
+    country(name, &[&ring])
+}
+
+/// Everything a query needs, as the interactive loop holds it.
+pub(crate) struct Solver {
+    pub(crate) countries: Vec<CountryData>,
+    pub(crate) cache: Arc<Mutex<DistanceCache>>,
+    pub(crate) config: Config,
+    pub(crate) session: Session,
+}
+
+impl Solver {
+    /// A solver over the synthetic fixture with default options.
+    pub(crate) fn fixture() -> Self {
+        Self::new(fixture_countries())
+    }
+
+    pub(crate) fn new(countries: Vec<CountryData>) -> Self {
+        let config = Config::default();
+        Self {
+            countries,
+            cache: Arc::new(Mutex::new(DistanceCache::new(config.cache_precision))),
+            config,
+            session: Session::default(),
+        }
+    }
+
+    /// Changes options, starting over with an empty cache.
+    pub(crate) fn with_config(mut self, change: impl FnOnce(&mut Config)) -> Self {
+        change(&mut self.config);
+        self.cache = Arc::new(Mutex::new(DistanceCache::new(self.config.cache_precision)));
+        self
+    }
+
+    pub(crate) fn country(&self, name: &str) -> &CountryData {
+        find_country(&self.countries, name).unwrap_or_else(|| panic!("no country named {}", name))
+    }
+
+    pub(crate) fn distance_km(&self, from: &str, to: &str) -> f64 {
+        cached_distance_km(&self.cache, self.country(from), self.country(to), &self.config)
+            .unwrap_or_else(|| panic!("no distance between {} and {}", from, to))
+    }
+
+    /// `distance` parsed as the prompt would, e.g. `1000--50`.
+    pub(crate) fn query(&self, distance: &str) -> DistanceInput {
+        parse_query(distance, &self.config).expect("a valid distance")
+    }
+
+    /// Countries matching one query exactly, without widening the margin or
+    /// touching the session.
+    pub(crate) fn search(&self, guess: &str, distance: &str) -> Vec<Candidate> {
+        let query = self.query(distance);
+        let progress = SearchProgress::default();
+        let cache = Arc::clone(&self.cache);
+        find_mystery_countries(self.country(guess), &query, &self.countries, cache, &self.config, &self.session, &progress)
+    }
+
+    /// Makes a guess as the interactive loop does, widening the margin when
+    /// too little matches, and returns the candidates' names.
+    pub(crate) fn guess(&mut self, guess: &str, distance: &str) -> Vec<String> {
+        let query = self.query(distance);
+        let country = self.country(guess).clone();
+        self.session.mark_guessed(&country.name);
+        let progress = SearchProgress::default();
+        let (query, candidates) =
+            search_with_expansion(&country, query, &self.countries, &self.cache, &self.config, &self.session, &progress);
+        // The prompt loop only records guesses that found something.
+        if !candidates.is_empty() {
+            self.session.record(&country.name, query, &candidates);
+        }
+        names(&candidates)
+    }
+
+    /// The session's remaining candidates, empty before the first guess.
+    pub(crate) fn remaining(&self) -> Vec<String> {
+        self.session.remaining.clone().unwrap_or_default()
+    }
+}
+
+pub(crate) fn names(candidates: &[Candidate]) -> Vec<String> {
+    candidates.iter().map(|candidate| candidate.name.clone()).collect()
+}
@@ -0,0 +1,27 @@
+//! `--check-data` run as a script would: the exit code says whether the data
+//! loads, and the first line says why.
+
+use std::process::Command;
+
+fn check_data(path: &str) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_untitled3"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--check-data", "--data", path])
+        .output()
+        .expect("failed to run the solver");
+    (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn a_valid_dataset_exits_zero() {
+    let (code, stdout) = check_data("fixtures/synthetic_countries.json");
+    assert_eq!(code, Some(0), "{}", stdout);
+    assert!(stdout.starts_with("OK: 5 countries loaded"), "{}", stdout);
+}
+
+#[test]
+fn a_missing_dataset_exits_nonzero() {
+    let (code, stdout) = check_data("fixtures/no_such_countries.json");
+    assert_eq!(code, Some(1), "{}", stdout);
+    assert!(stdout.starts_with("FAIL: Failed to open fixtures/no_such_countries.json"), "{}", stdout);
+}